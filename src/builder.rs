@@ -0,0 +1,542 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use reqwest::{redirect, Client, Identity, Url};
+use url::Host;
+use zeroize::Zeroizing;
+
+#[cfg(feature = "poll-loop")]
+use tokio::sync::Semaphore;
+
+#[cfg(feature = "poll-loop")]
+use super::poll::PollConfig;
+#[cfg(feature = "tower")]
+use super::transport::Transport;
+
+use super::{
+    cache::UserIdCache,
+    client::{Credentials, DuoClient, DuoClientInner, TimeSync},
+    endpoints,
+    errors::Error,
+    exchange::{CanonicalRequestHook, Exchange, ExchangeHook},
+    request::{CanonicalRequest, SignatureAlgorithm},
+};
+
+/// Per-endpoint request timeouts, `None` leaves the client's own timeout in effect
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    /// `/check` and `/ping`
+    pub check: Option<Duration>,
+    pub preauth: Option<Duration>,
+    pub auth: Option<Duration>,
+    /// Each individual `/auth_status` poll, see `with_poll_timeout` for the overall deadline.
+    /// A poll timing out is polled again, as Duo long-polls until the status changes.
+    pub auth_status: Option<Duration>,
+}
+
+impl Timeouts {
+    pub(crate) fn for_endpoint(&self, endpoint: &str) -> Option<Duration> {
+        match endpoint {
+            endpoints::CHECK | endpoints::PING => self.check,
+            endpoints::PREAUTH => self.preauth,
+            endpoints::AUTH => self.auth,
+            endpoints::AUTH_STATUS => self.auth_status,
+            _ => None,
+        }
+    }
+}
+
+/// Address family to restrict outgoing connections to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+pub struct DuoClientBuilder {
+    api_domain: String,
+    failover_domains: Vec<String>,
+    ikey: String,
+    skey: Zeroizing<String>,
+    client: Option<Client>,
+    api_version: String,
+    signature_algorithm: SignatureAlgorithm,
+    upgrade_signature_algorithm: bool,
+    sync_time: bool,
+    retry_on_clock_skew: bool,
+    max_retries: u32,
+    strict: bool,
+    halt_on_invalid_credentials: bool,
+    on_exchange: Option<ExchangeHook>,
+    on_canonical_request: Option<CanonicalRequestHook>,
+    local_address: Option<IpAddr>,
+    ip_family: Option<IpFamily>,
+    connect_timeout: Option<Duration>,
+    identity: Option<Identity>,
+    #[cfg(feature = "dangerous-tls")]
+    accept_invalid_certs: bool,
+    timeouts: Timeouts,
+    max_response_size: Option<usize>,
+    stale_preauth: Option<Duration>,
+    user_id_cache: Option<(Duration, usize)>,
+    warm_up_jitter: Option<Duration>,
+    #[cfg(feature = "poll-loop")]
+    poll: PollConfig,
+    #[cfg(feature = "poll-loop")]
+    max_concurrent_polls: Option<usize>,
+    #[cfg(feature = "poll-loop")]
+    fail_when_busy: bool,
+    #[cfg(feature = "poll-loop")]
+    count_outcomes: bool,
+    #[cfg(feature = "tower")]
+    transport: Option<Transport>,
+}
+
+impl DuoClientBuilder {
+    pub fn new<D, I, S>(api_domain: D, ikey: I, skey: S) -> Self
+    where
+        D: Into<String>,
+        I: Into<String>,
+        S: Into<String>,
+    {
+        Self {
+            api_domain: api_domain.into(),
+            failover_domains: Vec::new(),
+            ikey: ikey.into(),
+            skey: Zeroizing::new(skey.into()),
+            client: None,
+            api_version: endpoints::DEFAULT_API_VERSION.into(),
+            signature_algorithm: SignatureAlgorithm::default(),
+            upgrade_signature_algorithm: false,
+            sync_time: false,
+            retry_on_clock_skew: false,
+            max_retries: 0,
+            strict: false,
+            halt_on_invalid_credentials: false,
+            on_exchange: None,
+            on_canonical_request: None,
+            local_address: None,
+            ip_family: None,
+            connect_timeout: None,
+            identity: None,
+            #[cfg(feature = "dangerous-tls")]
+            accept_invalid_certs: false,
+            timeouts: Timeouts::default(),
+            max_response_size: None,
+            stale_preauth: None,
+            user_id_cache: None,
+            warm_up_jitter: None,
+            #[cfg(feature = "poll-loop")]
+            poll: PollConfig::default(),
+            #[cfg(feature = "poll-loop")]
+            max_concurrent_polls: None,
+            #[cfg(feature = "poll-loop")]
+            fail_when_busy: false,
+            #[cfg(feature = "poll-loop")]
+            count_outcomes: false,
+            #[cfg(feature = "tower")]
+            transport: None,
+        }
+    }
+
+    /// Sends requests with `client` instead of one built from the connection settings. Unlike
+    /// the built client, a custom one follows redirects unless built with
+    /// `redirect::Policy::none()`. Duo never redirects API requests; a followed redirect sends
+    /// the signed request wherever it points, and [`Error::UnexpectedRedirect`] isn't raised.
+    pub fn with_client<C: Into<Client>>(mut self, client: C) -> Self {
+        self.client = Some(client.into());
+        self
+    }
+
+    /// Secondary API host (e.g. of a proxying gateway) to fail over to when the previous ones
+    /// can't be connected to. Duo itself only supports the assigned API host.
+    pub fn with_failover_domain<D: Into<String>>(mut self, api_domain: D) -> Self {
+        self.failover_domains.push(api_domain.into());
+        self
+    }
+
+    /// Overrides the `/auth/<version>/` path prefix, defaults to `v2`
+    pub fn with_api_version<V: Into<String>>(mut self, api_version: V) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// Algorithm to sign requests with, defaults to HMAC-SHA1
+    pub fn with_signature_algorithm(mut self, algorithm: SignatureAlgorithm) -> Self {
+        self.signature_algorithm = algorithm;
+        self
+    }
+
+    /// When Duo rejects HMAC-SHA1 signed credentials, retry the request once signed with
+    /// HMAC-SHA512 and keep using that if accepted. Duo has no error code specific to the
+    /// algorithm, so any credential rejection triggers the retry.
+    pub fn upgrade_signature_algorithm(mut self, enabled: bool) -> Self {
+        self.upgrade_signature_algorithm = enabled;
+        self
+    }
+
+    /// Offset the signed `Date` header by the server time reported by `/auth/v2/ping`, synced
+    /// hourly before signing. When the ping fails the request is signed with the last known
+    /// offset (or the local clock) rather than failed, and a warning is logged.
+    pub fn sync_time(mut self, enabled: bool) -> Self {
+        self.sync_time = enabled;
+        self
+    }
+
+    /// On rejected credentials, resync the server time and retry the request once if the
+    /// local clock turned out to be off
+    pub fn retry_on_clock_skew(mut self, enabled: bool) -> Self {
+        self.retry_on_clock_skew = enabled;
+        self
+    }
+
+    /// Refuse to build insecure configurations with [`Error::InvalidConfig`]: non-https API
+    /// domains (except plain http to loopback addresses with the test-only `insecure-http`
+    /// feature), malformed integration credentials and disabled certificate verification. TLS
+    /// verification can't be checked with
+    /// [`DuoClientBuilder::with_client`], the crate's own client always verifies. Without
+    /// strict mode these configurations build, but raise a warning through `log`.
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// Once Duo rejects the credentials ([`Error::InvalidCredentials`]), fail every further
+    /// request with the same error without sending it, until `set_credentials` is called
+    pub fn halt_on_invalid_credentials(mut self, enabled: bool) -> Self {
+        self.halt_on_invalid_credentials = enabled;
+        self
+    }
+
+    /// Retry requests answered with a 5xx ([`Error::ServiceUnavailable`]) or rate limited (429)
+    /// up to `max_retries` times, backing off between attempts. Auth creation is never retried,
+    /// Duo may have created the transaction regardless and the user would be prompted twice.
+    /// Backing off needs the `poll-loop` feature's timer, without it retries are sent at once.
+    pub fn with_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Invoked with every sanitized HTTP exchange, e.g. to persist them for debugging
+    pub fn on_exchange<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Exchange) + Send + Sync + 'static,
+    {
+        self.on_exchange = Some(Arc::new(hook));
+        self
+    }
+
+    /// Invoked with the canonical form of every signed request before signing, e.g. to keep an
+    /// audit record. Credentials and the signature are never included, and passcodes, trusted
+    /// device tokens and activation codes are redacted.
+    pub fn on_canonical_request<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&CanonicalRequest) + Send + Sync + 'static,
+    {
+        self.on_canonical_request = Some(Arc::new(hook));
+        self
+    }
+
+    /// Source address for outgoing connections (ignored with [`DuoClientBuilder::with_client`])
+    pub fn with_local_address(mut self, address: IpAddr) -> Self {
+        self.local_address = Some(address);
+        self
+    }
+
+    /// Only connect over IPv4 or IPv6, skipping resolved addresses of the other family, e.g.
+    /// to avoid broken IPv6 egress. Superseded by [`DuoClientBuilder::with_local_address`] and
+    /// ignored with [`DuoClientBuilder::with_client`].
+    pub fn with_ip_family(mut self, family: IpFamily) -> Self {
+        self.ip_family = Some(family);
+        self
+    }
+
+    /// Timeout for establishing connections only (ignored with [`DuoClientBuilder::with_client`])
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Client certificate for mutual TLS, e.g. with a Duo Authentication Proxy requiring one
+    /// (ignored with [`DuoClientBuilder::with_client`])
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// **DANGER, for testing only.** Accepts any certificate Duo's host presents, including
+    /// self-signed and expired ones, so e.g. a local mock can serve TLS. Anyone on the network
+    /// path can then impersonate Duo and approve every auth. Refused by strict mode, and
+    /// ignored with [`DuoClientBuilder::with_client`].
+    #[cfg(feature = "dangerous-tls")]
+    pub fn danger_accept_invalid_certs(mut self, enabled: bool) -> Self {
+        self.accept_invalid_certs = enabled;
+        self
+    }
+
+    /// Overrides the request timeout per endpoint, e.g. to keep `check` snappy while allowing
+    /// a slow `auth`
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Fail API responses larger than `bytes` with [`Error::ResponseTooLarge`], bounding peak
+    /// memory e.g. for users with many devices. Unlimited by default.
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Remember each user's last preauth `auth` response for up to `max_age`, letting
+    /// `preauth_or_stale` fall back to it while Duo is unavailable. Off by default.
+    pub fn allow_stale_preauth(mut self, max_age: Duration) -> Self {
+        self.stale_preauth = Some(max_age);
+        self
+    }
+
+    /// Remember the user_id of up to `capacity` usernames for `ttl`, learned from enrollments
+    /// and preauth responses including it, and send preauth and auth requests for these users
+    /// by id. Entries are dropped when Duo rejects the id or preauth asks to enroll the
+    /// username; renames can't be detected, so keep `ttl` short. Off by default.
+    pub fn with_user_id_cache(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.user_id_cache = Some((ttl, capacity));
+        self
+    }
+
+    /// Delay `warm_up` by a random duration up to `max`, so instances started together don't
+    /// all hit Duo at once. No delay by default. Sleeping needs the `poll-loop` feature's timer,
+    /// without it this is a no-op.
+    pub fn with_warm_up_jitter(mut self, max: Duration) -> Self {
+        self.warm_up_jitter = Some(max);
+        self
+    }
+
+    /// Polling of the auth and enroll loops, see [`PollConfig`] for the defaults
+    #[cfg(feature = "poll-loop")]
+    pub fn with_poll_config(mut self, config: PollConfig) -> Self {
+        self.poll = config;
+        self
+    }
+
+    /// Delay between polls, see [`PollConfig::interval`]
+    #[cfg(feature = "poll-loop")]
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll.interval = interval;
+        self
+    }
+
+    /// Overall time to wait for an auth decision before failing with [`Error::Timeout`], see
+    /// [`PollConfig::timeout`]
+    #[cfg(feature = "poll-loop")]
+    pub fn with_poll_timeout(mut self, timeout: Duration) -> Self {
+        self.poll.timeout = Some(timeout);
+        self
+    }
+
+    /// Caps the number of auth poll loops running at once across the client. Auths beyond the
+    /// cap wait for a free slot before being sent to Duo, see `fail_when_busy` to reject them
+    /// instead. Unlimited by default.
+    #[cfg(feature = "poll-loop")]
+    pub fn with_max_concurrent_polls(mut self, limit: usize) -> Self {
+        self.max_concurrent_polls = Some(limit);
+        self
+    }
+
+    /// Fail auths beyond `with_max_concurrent_polls` with [`Error::Busy`] instead of queueing
+    #[cfg(feature = "poll-loop")]
+    pub fn fail_when_busy(mut self, enabled: bool) -> Self {
+        self.fail_when_busy = enabled;
+        self
+    }
+
+    /// Tally the final outcome of every auth poll loop, readable with `DuoClient::stats`. Off
+    /// by default, nothing is counted until enabled.
+    #[cfg(feature = "poll-loop")]
+    pub fn count_outcomes(mut self, enabled: bool) -> Self {
+        self.count_outcomes = enabled;
+        self
+    }
+
+    /// Sends requests through `service` instead of the HTTP client, e.g. a `reqwest::Client`
+    /// wrapped in tower middleware. Requests are still built (and signed) with the configured
+    /// client, so connection settings like `with_local_address` only apply to it.
+    #[cfg(feature = "tower")]
+    pub fn with_transport<S>(mut self, service: S) -> Self
+    where
+        S: tower::Service<reqwest::Request, Response = reqwest::Response> + Clone + Send + 'static,
+        S::Error: Into<tower::BoxError>,
+        S::Future: Send + 'static,
+    {
+        use tower::{util::BoxCloneService, ServiceExt};
+
+        let service = BoxCloneService::new(service.map_err(Into::into));
+        self.transport = Some(Transport::new(service));
+        self
+    }
+
+    pub fn build(mut self) -> Result<DuoClient, Error> {
+        #[cfg(feature = "poll-loop")]
+        self.poll.validate()?;
+
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => self.build_client()?,
+        };
+
+        let base_url = parse_api_domain(self.api_domain)?;
+        let failover_urls = self
+            .failover_domains
+            .into_iter()
+            .map(parse_api_domain)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        #[cfg(feature = "dangerous-tls")]
+        let insecure_tls = self
+            .accept_invalid_certs
+            .then(|| "TLS certificate verification must not be disabled".to_string());
+        #[cfg(not(feature = "dangerous-tls"))]
+        let insecure_tls = None;
+        let insecure = insecure_tls.or_else(|| {
+            std::iter::once(&base_url)
+                .chain(&failover_urls)
+                .find_map(|url| insecurity(url, &self.ikey, &self.skey))
+        });
+        match insecure {
+            Some(reason) if self.strict => return Err(Error::InvalidConfig { reason }),
+            Some(reason) => log::warn!("Insecure Duo client configuration: {reason}"),
+            None => {}
+        }
+
+        Ok(DuoClient::from_inner(DuoClientInner {
+            base_url,
+            failover_urls,
+            credentials: RwLock::new(Credentials {
+                generation: 0,
+                ikey: self.ikey,
+                skey: self.skey,
+            }),
+            halt_on_invalid_credentials: self.halt_on_invalid_credentials,
+            rejected_credentials: Default::default(),
+            api_version: self.api_version,
+            signature_algorithm: RwLock::new(self.signature_algorithm),
+            upgrade_signature_algorithm: self.upgrade_signature_algorithm,
+            time_sync: (self.sync_time || self.retry_on_clock_skew)
+                .then(|| TimeSync::new(self.sync_time)),
+            retry_on_clock_skew: self.retry_on_clock_skew,
+            max_retries: self.max_retries,
+            shutdown: Default::default(),
+            on_exchange: self.on_exchange,
+            on_canonical_request: self.on_canonical_request,
+            last_metadata: Default::default(),
+            timeouts: self.timeouts,
+            max_response_size: self.max_response_size,
+            stale_preauth: self.stale_preauth,
+            preauth_cache: Default::default(),
+            user_ids: self
+                .user_id_cache
+                .map(|(ttl, capacity)| UserIdCache::new(ttl, capacity)),
+            warm_up_jitter: self.warm_up_jitter,
+            #[cfg(feature = "poll-loop")]
+            poll: self.poll,
+            #[cfg(feature = "poll-loop")]
+            poll_slots: self
+                .max_concurrent_polls
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+            #[cfg(feature = "poll-loop")]
+            fail_when_busy: self.fail_when_busy,
+            #[cfg(feature = "poll-loop")]
+            outcomes: self.count_outcomes.then(Default::default),
+            #[cfg(feature = "tower")]
+            transport: self.transport,
+            client,
+        }))
+    }
+
+    fn build_client(&self) -> Result<Client, Error> {
+        let mut client = Client::builder()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .redirect(redirect::Policy::none())
+            .local_address(self.local_address.or_else(|| self.unspecified_address()));
+
+        if let Some(timeout) = self.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+        if let Some(identity) = self.identity.clone() {
+            client = client.identity(identity);
+        }
+        #[cfg(feature = "dangerous-tls")]
+        if self.accept_invalid_certs {
+            client = client.danger_accept_invalid_certs(true);
+        }
+
+        Ok(client.build()?)
+    }
+
+    // Binding to the unspecified address of a family makes the connector skip addresses of
+    // the other one
+    fn unspecified_address(&self) -> Option<IpAddr> {
+        match self.ip_family? {
+            IpFamily::V4 => Some(Ipv4Addr::UNSPECIFIED.into()),
+            IpFamily::V6 => Some(Ipv6Addr::UNSPECIFIED.into()),
+        }
+    }
+}
+
+fn parse_api_domain(api_domain: String) -> Result<Url, Error> {
+    let base_url = match Url::parse(&api_domain) {
+        Ok(url) => url,
+        Err(err) => {
+            return Err(Error::InvalidApiDomain {
+                domain: api_domain,
+                cause: err.into(),
+            })
+        }
+    };
+
+    // Fail fast when there's no domain
+    if base_url.host_str().is_none() {
+        return Err(Error::InvalidApiDomain {
+            domain: api_domain,
+            cause: "no domain in url".into(),
+        });
+    }
+
+    Ok(base_url)
+}
+
+/// First problem strict mode refuses to build with
+fn insecurity(base_url: &Url, ikey: &str, skey: &str) -> Option<String> {
+    let reason = if base_url.scheme() != "https" && !is_local_http(base_url) {
+        format!("API domain must use https, not {}", base_url.scheme())
+    } else if ikey.len() != 20 || !ikey.starts_with("DI") {
+        "integration key must be 20 characters starting with 'DI'".into()
+    } else if !ikey.chars().all(|c| c.is_ascii_alphanumeric()) {
+        "integration key must be alphanumeric".into()
+    } else if skey.len() != 40 {
+        "secret key must be 40 characters".into()
+    } else {
+        return None;
+    };
+
+    Some(reason)
+}
+
+/// Plain http to a loopback address, which strict mode accepts with `insecure-http` only
+fn is_local_http(url: &Url) -> bool {
+    let loopback = match url.host() {
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        Some(Host::Domain(domain)) => domain == "localhost",
+        None => false,
+    };
+
+    cfg!(feature = "insecure-http") && url.scheme() == "http" && loopback
+}
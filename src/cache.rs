@@ -0,0 +1,53 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Bounded username to user_id map with expiring entries
+pub(crate) struct UserIdCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl UserIdCache {
+    pub(crate) fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Default::default(),
+        }
+    }
+
+    pub(crate) fn get(&self, username: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        let (cached_at, user_id) = entries.get(username)?;
+        (cached_at.elapsed() <= self.ttl).then(|| user_id.clone())
+    }
+
+    pub(crate) fn insert(&self, username: String, user_id: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&username) {
+            entries.retain(|_, (cached_at, _)| cached_at.elapsed() <= self.ttl);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&username) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (cached_at, _))| *cached_at)
+                .map(|(username, _)| username.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(username, (Instant::now(), user_id));
+    }
+
+    pub(crate) fn remove(&self, username: &str) {
+        self.entries.lock().unwrap().remove(username);
+    }
+}
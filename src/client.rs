@@ -1,27 +1,316 @@
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use reqwest::{Client, Method, Request, Url};
+use chrono::{DateTime, Utc};
+use reqwest::{header, header::HeaderValue, Client, Method, Request, Response, StatusCode, Url};
 use serde::{de::DeserializeOwned, Deserialize};
+#[cfg(feature = "poll-loop")]
+use tokio::sync::{futures::Notified, Notify, Semaphore};
+use zeroize::Zeroizing;
 
+#[cfg(feature = "otel")]
+use super::otel;
+#[cfg(feature = "poll-loop")]
+use super::poll::{OutcomeCounters, PollConfig};
+#[cfg(feature = "tower")]
+use super::transport::Transport;
 use super::{
-    errors::Error,
-    request::{DuoRequest, Parameters},
-    response::DuoResponse,
-    types::PreauthResponse,
+    builder::{DuoClientBuilder, Timeouts},
+    cache::UserIdCache,
+    endpoints,
+    errors::{DuoErrorCode, Error, RequestContext},
+    exchange::{CanonicalRequestHook, Exchange, ExchangeHook, ExchangeRequest, ExchangeResponse},
+    request::{DuoRequest, Parameters, SignatureAlgorithm},
+    response::{DuoResponse, ResponseMetadata},
     types::{
-        AuthRequest, AuthStatusResponse, EnrollResponse, EnrollStatusResponse, PreauthRequest,
+        AuthDecision, AuthRequest, AuthRequestFactor, AuthStatusResponse, Device, DeviceCapability,
+        EnrollResponse, EnrollStatusResponse, PreauthOutcome, PreauthRequest, PreauthResponse,
+        PreauthResult, SavedAuth, Timestamp, Txid, User,
     },
-    StdError,
 };
 
-pub struct DuoClient(Arc<DuoClientInner>);
+/// Base delay between retries of 5xx and rate limited requests, grows linearly
+#[cfg(feature = "poll-loop")]
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+pub struct DuoClient(pub(crate) Arc<DuoClientInner>);
+
+pub(crate) struct DuoClientInner {
+    pub(crate) base_url: Url,
+    /// Tried in order when `base_url` can't be connected to
+    pub(crate) failover_urls: Vec<Url>,
+    pub(crate) credentials: RwLock<Credentials>,
+    pub(crate) halt_on_invalid_credentials: bool,
+    /// Rejection that halted the client, cleared by `set_credentials`
+    pub(crate) rejected_credentials: Mutex<Option<RejectedCredentials>>,
+    pub(crate) api_version: String,
+    pub(crate) signature_algorithm: RwLock<SignatureAlgorithm>,
+    pub(crate) upgrade_signature_algorithm: bool,
+    pub(crate) time_sync: Option<TimeSync>,
+    pub(crate) retry_on_clock_skew: bool,
+    pub(crate) max_retries: u32,
+    pub(crate) shutdown: Shutdown,
+    pub(crate) on_exchange: Option<ExchangeHook>,
+    pub(crate) on_canonical_request: Option<CanonicalRequestHook>,
+    pub(crate) last_metadata: Mutex<Option<ResponseMetadata>>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) max_response_size: Option<usize>,
+    /// Max age of `preauth_cache` entries, caching is off without
+    pub(crate) stale_preauth: Option<Duration>,
+    /// Last `auth` preauth response per user key
+    pub(crate) preauth_cache: Mutex<HashMap<String, (Instant, PreauthResponse)>>,
+    pub(crate) user_ids: Option<UserIdCache>,
+    pub(crate) warm_up_jitter: Option<Duration>,
+    #[cfg(feature = "poll-loop")]
+    pub(crate) poll: PollConfig,
+    #[cfg(feature = "poll-loop")]
+    pub(crate) poll_slots: Option<Arc<Semaphore>>,
+    #[cfg(feature = "poll-loop")]
+    pub(crate) fail_when_busy: bool,
+    #[cfg(feature = "poll-loop")]
+    /// Only with `count_outcomes`
+    pub(crate) outcomes: Option<OutcomeCounters>,
+    #[cfg(feature = "tower")]
+    pub(crate) transport: Option<Transport>,
+
+    pub(crate) client: reqwest::Client,
+}
+
+impl DuoClientInner {
+    fn halted_on_credentials(&self) -> Option<Error> {
+        let rejected = self.rejected_credentials.lock().unwrap();
+        rejected.as_ref().map(|rejected| Error::InvalidCredentials {
+            code: rejected.code,
+            message: rejected.message.clone(),
+            message_detail: rejected.message_detail.clone(),
+            context: None,
+        })
+    }
+
+    /// Halts the client on rejected credentials, unless they were replaced in the meantime
+    fn record_rejection(&self, err: &Error, generation: u64) {
+        let (code, message, message_detail) = match err {
+            Error::InvalidCredentials {
+                code,
+                message,
+                message_detail,
+                ..
+            } if self.halt_on_invalid_credentials => (code, message, message_detail),
+            _ => return,
+        };
+
+        let credentials = self.credentials.read().unwrap();
+        if credentials.generation == generation {
+            *self.rejected_credentials.lock().unwrap() = Some(RejectedCredentials {
+                code: *code,
+                message: message.clone(),
+                message_detail: message_detail.clone(),
+            });
+        }
+    }
+
+    /// `parameters` with the username swapped for its cached user_id, for endpoints taking either
+    fn resolve_user_id(
+        &self,
+        endpoint: &str,
+        parameters: &Parameters,
+    ) -> Option<(String, Parameters)> {
+        if !matches!(endpoint, endpoints::PREAUTH | endpoints::AUTH) {
+            return None;
+        }
+
+        let username = parameters.get("username")?;
+        let user_id = self.user_ids.as_ref()?.get(username)?;
+
+        let mut resolved = parameters.clone();
+        resolved.remove("username");
+        resolved.set("user_id", user_id);
+        Some((username.to_string(), resolved))
+    }
+
+    fn remember_user_id(&self, username: &str, user_id: &str) {
+        if let Some(user_ids) = &self.user_ids {
+            user_ids.insert(username.into(), user_id.into());
+        }
+    }
+
+    fn forget_user_id(&self, username: &str) {
+        if let Some(user_ids) = &self.user_ids {
+            user_ids.remove(username);
+        }
+    }
+
+    fn cache_preauth(&self, key: String, response: &PreauthResponse) {
+        let max_age = match self.stale_preauth {
+            Some(max_age) => max_age,
+            None => return,
+        };
+
+        let mut cache = self.preauth_cache.lock().unwrap();
+        cache.retain(|_, (cached_at, _)| cached_at.elapsed() <= max_age);
+        match response {
+            PreauthResponse::Auth { .. } => {
+                cache.insert(key, (Instant::now(), response.clone()));
+            }
+            _ => {
+                cache.remove(&key);
+            }
+        }
+    }
+
+    fn cached_preauth(&self, key: &str) -> Option<(Duration, PreauthResponse)> {
+        let max_age = self.stale_preauth?;
+        let cache = self.preauth_cache.lock().unwrap();
+        let (cached_at, response) = cache.get(key)?;
+
+        let age = cached_at.elapsed();
+        (age <= max_age).then(|| (age, response.clone()))
+    }
+}
+
+/// Answers worth sending the request again for: 5xx and rate limiting
+fn is_retried(err: &Error) -> bool {
+    match err {
+        Error::ServiceUnavailable { .. } => true,
+        Error::UnexpectedResponse { status, .. } => *status == 429,
+        err => err.duo_code() == Some(DuoErrorCode::RateLimited),
+    }
+}
+
+/// Endpoint and user the request is for. The user is identified by id or name only, never by
+/// any of the request's other parameters.
+fn request_context(endpoint: &str, parameters: &Parameters) -> RequestContext {
+    let user = ["user_id", "username"]
+        .iter()
+        .find_map(|k| parameters.get(k).map(|v| format!("{}={}", k, v)));
+
+    RequestContext {
+        endpoint: endpoint.to_string(),
+        user,
+    }
+}
+
+fn is_unknown_user_id(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::ApiRequestFailed {
+            code: DuoErrorCode::InvalidParameters,
+            message_detail: Some(detail),
+            ..
+        } if detail == "user_id"
+    )
+}
+
+pub(crate) struct RejectedCredentials {
+    code: DuoErrorCode,
+    message: String,
+    message_detail: Option<String>,
+}
+
+pub(crate) struct Credentials {
+    /// Bumped on every `set_credentials`
+    pub(crate) generation: u64,
+    pub(crate) ikey: String,
+    /// Wiped from memory when replaced or dropped
+    pub(crate) skey: Zeroizing<String>,
+}
+
+/// Uniformly random delay up to `max`, good enough to spread out clients started together
+fn random_delay(max: Duration) -> Duration {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+    };
+
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+#[derive(Default)]
+pub(crate) struct Shutdown {
+    requested: AtomicBool,
+    #[cfg(feature = "poll-loop")]
+    notify: Notify,
+}
+
+impl Shutdown {
+    fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        #[cfg(feature = "poll-loop")]
+        self.notify.notify_waiters();
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "poll-loop")]
+    pub(crate) fn notified(&self) -> Notified<'_> {
+        self.notify.notified()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TimeSync {
+    /// Sync before signing rather than only after signature failures
+    eager: bool,
+    offset_secs: AtomicI64,
+    /// Next time an eager sync is due, right away when unset
+    refresh_at: Mutex<Option<Instant>>,
+}
+
+impl TimeSync {
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    /// Wait after a failed sync, so an unreachable `/ping` doesn't delay every request
+    const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub(crate) fn new(eager: bool) -> Self {
+        Self {
+            eager,
+            ..Default::default()
+        }
+    }
+
+    fn needs_refresh(&self) -> bool {
+        if !self.eager {
+            return false;
+        }
 
-struct DuoClientInner {
-    base_url: Url,
-    ikey: String,
-    skey: String,
+        match *self.refresh_at.lock().unwrap() {
+            Some(at) => Instant::now() >= at,
+            None => true,
+        }
+    }
+
+    fn update(&self, server_time: Timestamp) {
+        let offset = server_time.as_secs() as i64 - Utc::now().timestamp();
+        self.offset_secs.store(offset, Ordering::Relaxed);
+        *self.refresh_at.lock().unwrap() = Some(Instant::now() + Self::REFRESH_INTERVAL);
+    }
+
+    /// Keeps the last known offset, or the local clock if never synced
+    fn failed(&self) {
+        *self.refresh_at.lock().unwrap() = Some(Instant::now() + Self::RETRY_INTERVAL);
+    }
 
-    client: reqwest::Client,
+    fn invalidate(&self) {
+        *self.refresh_at.lock().unwrap() = None;
+    }
+
+    fn offset_secs(&self) -> i64 {
+        self.offset_secs.load(Ordering::Relaxed)
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(self.offset_secs())
+    }
 }
 
 impl DuoClient {
@@ -31,18 +320,10 @@ impl DuoClient {
         I: Into<String>,
         S: Into<String>,
     {
-        let client = reqwest::Client::builder()
-            .user_agent(concat!(
-                env!("CARGO_PKG_NAME"),
-                "/",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .build()
-            .map_err(Error::unspecified)?;
-
-        Self::new_with_client(client, api_domain, ikey, skey)
+        DuoClientBuilder::new(api_domain, ikey, skey).build()
     }
 
+    /// Mind the redirect policy of `client`, see [`DuoClientBuilder::with_client`]
     pub fn new_with_client<C, D, I, S>(
         client: C,
         api_domain: D,
@@ -55,89 +336,152 @@ impl DuoClient {
         I: Into<String>,
         S: Into<String>,
     {
-        let api_domain = api_domain.into();
-
-        let base_url = match Url::parse(&api_domain) {
-            Ok(url) => url,
-            Err(err) => {
-                return Err(Error::InvalidApiDomain {
-                    domain: api_domain,
-                    cause: err.into(),
-                })
-            }
-        };
+        DuoClientBuilder::new(api_domain, ikey, skey)
+            .with_client(client)
+            .build()
+    }
 
-        // Fail fast when there's no domain
-        let _ = base_url
-            .host_str()
-            .ok_or_else(|| Error::InvalidApiDomain {
-                domain: api_domain,
-                cause: "no domain in url".into(),
-            })?
-            .to_string();
+    pub fn builder<D, I, S>(api_domain: D, ikey: I, skey: S) -> DuoClientBuilder
+    where
+        D: Into<String>,
+        I: Into<String>,
+        S: Into<String>,
+    {
+        DuoClientBuilder::new(api_domain, ikey, skey)
+    }
 
-        Ok(DuoClient(Arc::new(DuoClientInner {
-            base_url,
-            ikey: ikey.into(),
-            skey: skey.into(),
-            client: client.into(),
-        })))
+    pub(crate) fn from_inner(inner: DuoClientInner) -> Self {
+        DuoClient(Arc::new(inner))
     }
 
-    pub fn auth(&self, data: AuthRequest) -> impl Future<Output = Result<String, Error>> {
+    /// Starts an asynchronous auth transaction and returns its txid.
+    ///
+    /// Duo offers no idempotency key for `/auth/v2/auth`, so this is never retried, not even
+    /// with `with_retries`. If the request may have reached Duo but no answer was received,
+    /// including a 5xx, [`Error::AuthCreationUncertain`] is returned; retrying blindly could
+    /// push the user twice.
+    pub fn auth(&self, data: AuthRequest) -> impl Future<Output = Result<Txid, Error>> {
         let this = Arc::clone(&self.0);
 
         async move { Self::request_auth(this, data).await }
     }
 
-    pub fn auth_status<S: Into<String>>(
-        &self,
-        tx_id: S,
-    ) -> impl Future<Output = Result<AuthStatusResponse, Error>> {
+    /// Like [`DuoClient::auth`], returning the transaction in a form that can be persisted and
+    /// handed to `resume_poll` elsewhere
+    pub fn start_auth(&self, data: AuthRequest) -> impl Future<Output = Result<SavedAuth, Error>> {
         let this = Arc::clone(&self.0);
 
         async move {
-            let txid: String = tx_id.into();
-            Self::request_auth_status(this, &txid).await
+            let txid = Self::request_auth(this, data).await?;
+            Ok(SavedAuth {
+                txid,
+                created_at: Timestamp::now(),
+            })
         }
     }
 
-    pub fn auth_wait(&self, data: AuthRequest) -> impl Future<Output = Result<bool, StdError>> {
+    /// Sends a new batch of SMS passcodes to `device`, regardless of unused ones. Duo has no
+    /// separate "next passcode" request; preauth's [`Device::sms_nextcode`] tells which
+    /// passcode of the current batch is next. Duo answers right away, so there is nothing to
+    /// poll and no poll loop slot is taken.
+    pub fn send_sms_passcodes<D: Into<String>>(
+        &self,
+        user: User,
+        device: D,
+    ) -> impl Future<Output = Result<(), Error>> {
         let this = Arc::clone(&self.0);
+        let factor = AuthRequestFactor::Sms {
+            device: device.into(),
+        };
 
         async move {
-            let txid = Self::request_auth(this.clone(), data).await?;
-            let mut status: Option<bool>;
-
-            loop {
-                status = Self::request_auth_status(this.clone(), &txid)
-                    .await?
-                    .ready();
-                match status {
-                    None => tokio::time::sleep(Duration::from_secs(2)).await,
-                    Some(v) => return Ok(v),
-                }
+            let response = Self::request_auth_sync(this, AuthRequest::new(user, factor)).await?;
+
+            match response.decision() {
+                Some(AuthDecision::PasscodesSent) => Ok(()),
+                _ => Err(Error::PasscodesNotSent {
+                    status: response.status,
+                    status_msg: response.status_msg,
+                }),
             }
         }
     }
 
-    pub fn check(&self) -> impl Future<Output = Result<u64, Error>> {
+    pub fn auth_status(
+        &self,
+        txid: &Txid,
+    ) -> impl Future<Output = Result<AuthStatusResponse, Error>> {
+        let this = Arc::clone(&self.0);
+        let txid = txid.clone();
+
+        async move { Self::request_auth_status(this, &txid).await }
+    }
+
+    /// Untouched `response` object of `/auth/v2/auth_status`, for fields not modelled yet
+    pub fn auth_status_raw(
+        &self,
+        txid: &Txid,
+    ) -> impl Future<Output = Result<serde_json::Value, Error>> {
+        let this = Arc::clone(&self.0);
+        let txid = txid.clone();
+
+        async move { Self::request_auth_status(this, &txid).await }
+    }
+
+    /// Swaps the integration credentials in place. Requests already signed keep using the
+    /// previous ones. Lifts a halt from `halt_on_invalid_credentials`.
+    pub fn set_credentials<I, S>(&self, ikey: I, skey: S)
+    where
+        I: Into<String>,
+        S: Into<String>,
+    {
+        let mut credentials = self.0.credentials.write().unwrap();
+        *credentials = Credentials {
+            generation: credentials.generation + 1,
+            ikey: ikey.into(),
+            skey: Zeroizing::new(skey.into()),
+        };
+        *self.0.rejected_credentials.lock().unwrap() = None;
+    }
+
+    /// Rate limit and `X-Duo-*` headers of the most recently received response, e.g. to shed
+    /// load before hitting 429s
+    pub fn last_response_metadata(&self) -> Option<ResponseMetadata> {
+        self.0.last_metadata.lock().unwrap().clone()
+    }
+
+    /// Stops accepting new auths; in-flight polls return [`Error::ShuttingDown`] instead of
+    /// waiting for the user
+    pub fn shutdown(&self) {
+        self.0.shutdown.request();
+    }
+
+    /// Verifies the integration credentials, returning the server time. This is all the Auth
+    /// API reports; Universal Prompt belongs to the Web SDK (OIDC) flow and its state or version
+    /// is not exposed here.
+    pub fn check(&self) -> impl Future<Output = Result<Timestamp, Error>> {
         let this = Arc::clone(&self.0);
 
         async move {
             #[derive(Deserialize, Debug)]
             struct CheckResponse {
-                time: u64,
+                time: Timestamp,
             }
 
-            let request =
-                Self::new_request(&this, Method::GET, "/auth/v2/check", Parameters::default())?;
-            Self::send_request_json::<CheckResponse>(&this.client, request)
-                .await
-                .map(|r| r.time)
+            Self::signed_request_json::<CheckResponse>(
+                &this,
+                Method::GET,
+                endpoints::CHECK,
+                Parameters::default(),
+            )
+            .await
+            .map(|r| r.time)
         }
     }
 
+    /// Creates a user for enrollment. With a `username` Duo creates that user, failing with
+    /// [`Error::UsernameTaken`] if it already exists; without one Duo generates a unique
+    /// username, returned in [`EnrollResponse::username`].
     pub fn enroll<U: Into<String>>(
         &self,
         username: Option<U>,
@@ -148,6 +492,37 @@ impl DuoClient {
         async move { Self::request_enroll(this, username, valid_secs).await }
     }
 
+    /// Downloads the activation QR code (PNG) so it can be served without exposing its URL.
+    /// Fails with [`Error::UntrustedBarcodeUrl`] unless the URL has the API domain's scheme,
+    /// host and port.
+    pub fn fetch_enrollment_qr(
+        &self,
+        enrollment: &EnrollResponse,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+        let this = Arc::clone(&self.0);
+        let barcode_url = enrollment.activation_barcode.clone();
+
+        async move {
+            // Only ever fetch from the configured API origin, never e.g. over plain http
+            let url = match Url::parse(&barcode_url) {
+                Ok(url) if url.origin() == this.base_url.origin() => url,
+                Ok(url) => {
+                    return Err(Error::UntrustedBarcodeUrl {
+                        origin: url.origin().ascii_serialization(),
+                    })
+                }
+                Err(_) => {
+                    return Err(Error::UntrustedBarcodeUrl {
+                        origin: "<invalid URL>".into(),
+                    })
+                }
+            };
+
+            let request = this.client.get(url).build()?;
+            Self::send_request_bytes(&this, request).await
+        }
+    }
+
     pub fn enroll_status<U: Into<String>, A: Into<String>>(
         &self,
         user_id: U,
@@ -158,23 +533,48 @@ impl DuoClient {
         async move { Self::request_enroll_status(this, user_id, activation_code).await }
     }
 
-    pub fn ping(&self) -> impl Future<Output = Result<u64, Error>> {
+    pub fn ping(&self) -> impl Future<Output = Result<Timestamp, Error>> {
         let this = Arc::clone(&self.0);
 
         async move {
             #[derive(Deserialize, Debug)]
             struct PingResponse {
-                time: u64,
+                time: Timestamp,
             }
 
-            let request =
-                Self::new_request(&this, Method::GET, "/auth/v2/ping", Parameters::default())?;
-            Self::send_request_json::<PingResponse>(&this.client, request)
-                .await
-                .map(|r| r.time)
+            Self::signed_request_json::<PingResponse>(
+                &this,
+                Method::GET,
+                endpoints::PING,
+                Parameters::default(),
+            )
+            .await
+            .map(|r| r.time)
+        }
+    }
+
+    /// Establishes a pooled connection to Duo ahead of the first real request, after the
+    /// configured startup jitter (skipped without the `poll-loop` feature). Failing to warm up leaves the client fully usable; the error
+    /// is only informational.
+    pub fn warm_up(&self) -> impl Future<Output = Result<(), Error>> {
+        let jitter = self.0.warm_up_jitter.map(random_delay);
+        let ping = self.ping();
+
+        async move {
+            #[cfg(feature = "poll-loop")]
+            if let Some(delay) = jitter {
+                tokio::time::sleep(delay).await;
+            }
+            #[cfg(not(feature = "poll-loop"))]
+            let _ = jitter;
+
+            ping.await.map(|_| ())
         }
     }
 
+    /// Preauth neither prompts the user nor counts as an authentication attempt, but Duo may
+    /// still apply policy side effects (e.g. creating a pending enrollment for unknown users
+    /// when new user policy is set to "require enrollment"). There is no parameter to opt out.
     pub fn preauth(
         &self,
         data: PreauthRequest,
@@ -184,47 +584,279 @@ impl DuoClient {
         async move { Self::request_preauth(this, data).await }
     }
 
-    async fn request_auth(this: Arc<DuoClientInner>, data: AuthRequest) -> Result<String, Error> {
-        let mut parameters = Parameters::default();
-        parameters.set("async", "1");
-        data.apply(&mut parameters);
+    /// Untouched `response` object of `/auth/v2/preauth`, for fields not modelled yet. Duo
+    /// documents no risk assessment for the Auth API, but any signals it may attach (e.g. with
+    /// Risk-Based Authentication) are kept here.
+    pub fn preauth_raw(
+        &self,
+        data: PreauthRequest,
+    ) -> impl Future<Output = Result<serde_json::Value, Error>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let mut parameters = Parameters::default();
+            data.apply(&mut parameters)?;
+
+            Self::signed_request_json(&this, Method::POST, endpoints::PREAUTH, parameters).await
+        }
+    }
+
+    /// Like [`DuoClient::preauth`], falling back to the user's last `auth` response when Duo is
+    /// unavailable and `allow_stale_preauth` is set. Cached responses are marked stale; allow,
+    /// deny and enroll responses are never cached, and evict the user's entry.
+    pub fn preauth_or_stale(
+        &self,
+        data: PreauthRequest,
+    ) -> impl Future<Output = Result<PreauthOutcome, Error>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let key = data.user.key();
+            match Self::request_preauth(this.clone(), data).await {
+                Ok(response) => Ok(PreauthOutcome {
+                    response,
+                    stale_for: None,
+                }),
+                Err(err) if err.is_unavailable() => match this.cached_preauth(&key) {
+                    Some((age, response)) => Ok(PreauthOutcome {
+                        response,
+                        stale_for: Some(age),
+                    }),
+                    None => Err(err),
+                },
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    /// Like [`DuoClient::preauth`], only deserializing the decision and skipping the devices
+    pub fn preauth_result(
+        &self,
+        data: PreauthRequest,
+    ) -> impl Future<Output = Result<PreauthResult, Error>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            #[derive(Deserialize, Debug)]
+            struct ResultOnly {
+                result: PreauthResult,
+            }
 
+            let mut parameters = Parameters::default();
+            data.apply(&mut parameters)?;
+
+            Self::signed_request_json::<ResultOnly>(
+                &this,
+                Method::POST,
+                endpoints::PREAUTH,
+                parameters,
+            )
+            .await
+            .map(|r| r.result)
+        }
+    }
+
+    /// Whether the user has completed Duo enrollment, i.e. preauth doesn't ask for enrollment.
+    /// Users let through or denied by policy count as enrolled.
+    pub fn is_enrolled(&self, user: User) -> impl Future<Output = Result<bool, Error>> {
+        let result = self.preauth_result(PreauthRequest::new(user));
+
+        async move { Ok(result.await? != PreauthResult::Enroll) }
+    }
+
+    /// Devices the user can authenticate with, empty unless preauth requires a second factor
+    pub fn list_devices(&self, user: User) -> impl Future<Output = Result<Vec<Device>, Error>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            match Self::request_preauth(this, PreauthRequest::new(user)).await? {
+                PreauthResponse::Auth { devices, .. } => Ok(devices),
+                _ => Ok(Vec::new()),
+            }
+        }
+    }
+
+    /// Usable factors across all of the user's devices, strongest first. Empty unless preauth
+    /// requires a second factor.
+    pub fn available_factors(
+        &self,
+        user: User,
+    ) -> impl Future<Output = Result<Vec<DeviceCapability>, Error>> {
+        let devices = self.list_devices(user);
+
+        async move {
+            let devices = devices.await?;
+            Ok(DeviceCapability::STRENGTH_ORDER
+                .into_iter()
+                .filter(|c| devices.iter().any(|d| d.supports(*c)))
+                .collect())
+        }
+    }
+
+    /// Device and capability to authenticate with, picked by `preference` (strongest first
+    /// with [`DeviceCapability::STRENGTH_ORDER`])
+    pub fn recommend_device(
+        &self,
+        user: User,
+        preference: &[DeviceCapability],
+    ) -> impl Future<Output = Result<Option<(Device, DeviceCapability)>, Error>> {
+        let devices = self.list_devices(user);
+        let preference = preference.to_vec();
+
+        async move {
+            let devices = devices.await?;
+            Ok(Device::recommend(&devices, &preference).map(|(d, c)| (d.clone(), c)))
+        }
+    }
+
+    pub(crate) async fn request_auth(
+        this: Arc<DuoClientInner>,
+        data: AuthRequest,
+    ) -> Result<Txid, Error> {
         #[derive(Deserialize, Debug)]
         struct AuthResponse {
-            txid: String,
+            txid: Txid,
         }
 
-        let request = Self::new_request(&this, Method::POST, "/auth/v2/auth", parameters)?;
-        Self::send_request_json::<AuthResponse>(&this.client, request)
+        Self::send_auth::<AuthResponse>(&this, data, true)
             .await
             .map(|r| r.txid)
     }
 
-    async fn request_auth_status(
+    /// Auth answered right away rather than with a transaction to poll, only meant for
+    /// factors Duo doesn't prompt the user for (i.e. sending SMS passcodes)
+    async fn request_auth_sync(
         this: Arc<DuoClientInner>,
-        tx_id: &str,
+        data: AuthRequest,
     ) -> Result<AuthStatusResponse, Error> {
+        Self::send_auth(&this, data, false).await
+    }
+
+    async fn send_auth<T>(
+        this: &Arc<DuoClientInner>,
+        data: AuthRequest,
+        asynchronous: bool,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        if this.shutdown.is_requested() {
+            return Err(Error::ShuttingDown);
+        }
+
+        let device = data.factor.device().map(String::from);
         let mut parameters = Parameters::default();
-        parameters.set("txid", tx_id);
+        if asynchronous {
+            parameters.set("async", "1");
+        }
+        data.apply(&mut parameters)?;
 
-        let request = Self::new_request(&this, Method::GET, "/auth/v2/auth_status", parameters)?;
-        Self::send_request_json(&this.client, request).await
+        Self::signed_request_json::<T>(this, Method::POST, endpoints::AUTH, parameters)
+            .await
+            .map_err(|err| match err {
+                // Failing after connecting (e.g. a timeout) leaves it open whether Duo created
+                // the transaction. Connect errors are passed on, nothing reached Duo.
+                Error::Http(err) if !err.is_connect() => Error::AuthCreationUncertain {
+                    cause: err.into(),
+                    context: None,
+                },
+                // e.g. a proxy timing out after Duo created the transaction
+                Error::ServiceUnavailable { status, context } => Error::AuthCreationUncertain {
+                    cause: Error::ServiceUnavailable {
+                        status,
+                        context: None,
+                    }
+                    .into(),
+                    context,
+                },
+                Error::ApiRequestFailed {
+                    code: DuoErrorCode::InvalidParameters,
+                    message_detail: Some(ref detail),
+                    context,
+                    ..
+                } if detail == "device" => Error::UnknownDevice {
+                    device: device.unwrap_or_default(),
+                    context,
+                },
+                err => err,
+            })
     }
 
-    async fn request_enroll<U: Into<String>>(
+    pub(crate) async fn request_auth_status<T>(
+        this: Arc<DuoClientInner>,
+        tx_id: &Txid,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let mut parameters = Parameters::default();
+        parameters.set("txid", tx_id.as_str());
+
+        let response: serde_json::Value =
+            Self::signed_request_json(&this, Method::GET, endpoints::AUTH_STATUS, parameters)
+                .await
+                .map_err(|err| match err {
+                    // Expired or never existing transactions are rejected as an invalid txid
+                    Error::ApiRequestFailed {
+                        code: DuoErrorCode::InvalidParameters,
+                        message_detail: Some(ref detail),
+                        context,
+                        ..
+                    } if detail == "txid" => Error::InvalidTransaction {
+                        txid: tx_id.to_string(),
+                        context,
+                    },
+                    err => err,
+                })?;
+
+        // Duo doesn't currently echo the txid, but never accept a response for another one
+        if let Some(echoed) = response.get("txid").and_then(|v| v.as_str()) {
+            if echoed != tx_id.as_str() {
+                return Err(Error::ResponseMismatch {
+                    expected: tx_id.to_string(),
+                    actual: echoed.to_string(),
+                    context: Some(RequestContext {
+                        endpoint: endpoints::AUTH_STATUS.into(),
+                        user: None,
+                    }),
+                });
+            }
+        }
+
+        serde_json::from_value(response).map_err(Error::unspecified)
+    }
+
+    pub(crate) async fn request_enroll<U: Into<String>>(
         this: Arc<DuoClientInner>,
         username: Option<U>,
         valid_secs: Option<u64>,
     ) -> Result<EnrollResponse, Error> {
+        let username = username.map(Into::into);
         let mut parameters = Parameters::default();
-        parameters.set_opt("username", username);
+        parameters.set_opt("username", username.clone());
         parameters.set_opt("valid_secs", valid_secs.map(|v| v.to_string()));
 
-        let request = Self::new_request(&this, Method::POST, "/auth/v2/enroll", parameters)?;
-        Self::send_request_json(&this.client, request).await
+        let enrollment: EnrollResponse =
+            Self::signed_request_json(&this, Method::POST, endpoints::ENROLL, parameters)
+                .await
+                .map_err(|err| match (err, username) {
+                    (
+                        Error::ApiRequestFailed {
+                            code: DuoErrorCode::InvalidParameters,
+                            message_detail: Some(ref detail),
+                            context,
+                            ..
+                        },
+                        Some(username),
+                    ) if detail == "username" => Error::UsernameTaken { username, context },
+                    (err, _) => err,
+                })?;
+
+        this.remember_user_id(&enrollment.username, &enrollment.user_id);
+        Ok(enrollment)
     }
 
-    async fn request_enroll_status<U: Into<String>, A: Into<String>>(
+    pub(crate) async fn request_enroll_status<U: Into<String>, A: Into<String>>(
         this: Arc<DuoClientInner>,
         user_id: U,
         activation_code: A,
@@ -233,43 +865,490 @@ impl DuoClient {
         parameters.set("user_id", user_id);
         parameters.set("activation_code", activation_code);
 
-        let request = Self::new_request(&this, Method::POST, "/auth/v2/enroll_status", parameters)?;
-        Self::send_request_json(&this.client, request).await
+        Self::signed_request_json(&this, Method::POST, endpoints::ENROLL_STATUS, parameters).await
     }
 
-    async fn request_preauth(
+    pub(crate) async fn request_preauth(
         this: Arc<DuoClientInner>,
         data: PreauthRequest,
     ) -> Result<PreauthResponse, Error> {
+        let key = this.stale_preauth.map(|_| data.user.key());
+        let username = match &data.user {
+            User::Username { username } => Some(username.clone()),
+            User::UserId { .. } => None,
+        };
         let mut parameters = Parameters::default();
-        data.apply(&mut parameters);
+        data.apply(&mut parameters)?;
+
+        let response: PreauthResponse =
+            Self::signed_request_json(&this, Method::POST, endpoints::PREAUTH, parameters).await?;
+        if let Some(key) = key {
+            this.cache_preauth(key, &response);
+        }
+        if let Some(username) = username {
+            match (&response, response.user_id()) {
+                // The user_id may belong to a deleted user, a new one could have taken the username
+                (PreauthResponse::Enroll { .. }, _) => this.forget_user_id(&username),
+                (_, Some(user_id)) => this.remember_user_id(&username, user_id),
+                _ => {}
+            }
+        }
 
-        let request = Self::new_request(&this, Method::POST, "/auth/v2/preauth", parameters)?;
-        Self::send_request_json(&this.client, request).await
+        Ok(response)
     }
 
-    fn new_request<P: Into<String>>(
+    async fn signed_request_json<T>(
         this: &Arc<DuoClientInner>,
         method: Method,
-        path: P,
+        endpoint: &str,
+        parameters: Parameters,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let context = request_context(endpoint, &parameters);
+        let with_context = |err: Error| err.with_context(&context);
+
+        if let Some(err) = this.halted_on_credentials() {
+            return Err(with_context(err));
+        }
+
+        // Users are looked up by id once known, unless Duo no longer knows the id
+        if let Some((username, resolved)) = this.resolve_user_id(endpoint, &parameters) {
+            match Self::signed_request_json_retrying(this, &method, endpoint, &resolved).await {
+                Err(err) if is_unknown_user_id(&err) => this.forget_user_id(&username),
+                result => return result.map_err(with_context),
+            }
+        }
+
+        Self::signed_request_json_retrying(this, &method, endpoint, &parameters)
+            .await
+            .map_err(with_context)
+    }
+
+    async fn signed_request_json_retrying<T>(
+        this: &Arc<DuoClientInner>,
+        method: &Method,
+        endpoint: &str,
+        parameters: &Parameters,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let generation = this.credentials.read().unwrap().generation;
+        let result =
+            Self::signed_request_json_backing_off(this, method, endpoint, parameters).await;
+
+        if let Err(err) = &result {
+            this.record_rejection(err, generation);
+        }
+        result
+    }
+
+    /// Retries 5xx and rate limited answers up to `max_retries` times, except for auth
+    /// creation: Duo may have created the transaction regardless, see [`DuoClient::auth`].
+    /// Without the `poll-loop` feature there is no timer to back off with, so retries are
+    /// sent right away.
+    async fn signed_request_json_backing_off<T>(
+        this: &Arc<DuoClientInner>,
+        method: &Method,
+        endpoint: &str,
+        parameters: &Parameters,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let mut retries = 0;
+        loop {
+            match Self::signed_request_json_negotiated(this, method, endpoint, parameters).await {
+                Err(err)
+                    if retries < this.max_retries
+                        && endpoint != endpoints::AUTH
+                        && is_retried(&err) =>
+                {
+                    retries += 1;
+                    #[cfg(feature = "poll-loop")]
+                    tokio::time::sleep(RETRY_BACKOFF * retries).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Signs with the current algorithm, upgrading to HMAC-SHA512 for good when Duo rejects
+    /// HMAC-SHA1 credentials but accepts these, if enabled
+    async fn signed_request_json_negotiated<T>(
+        this: &Arc<DuoClientInner>,
+        method: &Method,
+        endpoint: &str,
+        parameters: &Parameters,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let algorithm = *this.signature_algorithm.read().unwrap();
+        let result =
+            Self::signed_request_json_failover(this, algorithm, method, endpoint, parameters).await;
+
+        match result {
+            Err(Error::InvalidCredentials { .. })
+                if this.upgrade_signature_algorithm
+                    && algorithm == SignatureAlgorithm::HmacSha1 =>
+            {
+                let upgraded = SignatureAlgorithm::HmacSha512;
+                let result = Self::signed_request_json_failover(
+                    this, upgraded, method, endpoint, parameters,
+                )
+                .await;
+                if result.is_ok() {
+                    *this.signature_algorithm.write().unwrap() = upgraded;
+                }
+                result
+            }
+            result => result,
+        }
+    }
+
+    /// Tries the API host and then each failover host in turn for as long as connecting fails.
+    /// Nothing reached Duo then, so this is safe even for auth creation.
+    async fn signed_request_json_failover<T>(
+        this: &Arc<DuoClientInner>,
+        algorithm: SignatureAlgorithm,
+        method: &Method,
+        endpoint: &str,
+        parameters: &Parameters,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let mut failover = 0;
+        let mut base_url = &this.base_url;
+
+        loop {
+            let result = Self::signed_request_json_once(
+                this,
+                base_url,
+                algorithm,
+                method.clone(),
+                endpoint,
+                parameters.clone(),
+            )
+            .await;
+
+            match (result, this.failover_urls.get(failover)) {
+                (Err(Error::Http(err)), Some(next)) if err.is_connect() => {
+                    base_url = next;
+                    failover += 1;
+                }
+                // Nothing was sent either, the next host may well present a valid certificate
+                (Err(Error::Tls { .. }), Some(next)) => {
+                    base_url = next;
+                    failover += 1;
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+
+    async fn signed_request_json_once<T>(
+        this: &Arc<DuoClientInner>,
+        base_url: &Url,
+        algorithm: SignatureAlgorithm,
+        method: Method,
+        endpoint: &str,
+        parameters: Parameters,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let request = Self::new_request(
+            this,
+            base_url,
+            algorithm,
+            method.clone(),
+            endpoint,
+            parameters.clone(),
+        )
+        .await?;
+        let result = Self::send_request_json(this, request).await;
+
+        let time_sync = match (&result, &this.time_sync) {
+            (Err(Error::InvalidCredentials { .. }), Some(time_sync))
+                if this.retry_on_clock_skew =>
+            {
+                time_sync
+            }
+            _ => return result,
+        };
+
+        // Only worth retrying when the clock offset turned out to be different. Duo rejected
+        // the request outright, so retrying is safe even for auth creation. A failed resync
+        // leaves the rejection as the outcome.
+        let offset = time_sync.offset_secs();
+        let synced = Self::sync_time(this, base_url, time_sync).await;
+        if synced.is_err() || time_sync.offset_secs() == offset {
+            return result;
+        }
+
+        let request =
+            Self::new_request(this, base_url, algorithm, method, endpoint, parameters).await?;
+        Self::send_request_json(this, request).await
+    }
+
+    async fn new_request(
+        this: &Arc<DuoClientInner>,
+        base_url: &Url,
+        algorithm: SignatureAlgorithm,
+        method: Method,
+        endpoint: &str,
         parameters: Parameters,
     ) -> Result<Request, Error> {
-        DuoRequest::new(this.base_url.clone(), method, path, parameters)
-            .build(&this.client, &this.ikey, &this.skey)
-            .map_err(Error::unspecified)
+        let path = endpoints::path(&this.api_version, endpoint);
+        let mut request = DuoRequest::new(base_url.clone(), method, path, parameters)
+            .with_signature_algorithm(algorithm);
+
+        if let Some(time_sync) = &this.time_sync {
+            // The request may well go through without, Duo allows some clock drift
+            if time_sync.needs_refresh() {
+                if let Err(err) = Self::sync_time(this, base_url, time_sync).await {
+                    log::warn!("Failed to sync time with Duo, signing with the local clock: {err}");
+                    time_sync.failed();
+                }
+            }
+            request = request.with_date(time_sync.now());
+        }
+
+        if let Some(hook) = &this.on_canonical_request {
+            hook(&request.canonical());
+        }
+
+        let mut request = {
+            let credentials = this.credentials.read().unwrap();
+            request
+                .build(&this.client, &credentials.ikey, &credentials.skey)
+                .map_err(Error::unspecified)?
+        };
+        if let Some(timeout) = this.timeouts.for_endpoint(endpoint) {
+            *request.timeout_mut() = Some(timeout);
+        }
+
+        Ok(request)
     }
 
-    async fn send_request_json<T>(client: &Client, request: Request) -> Result<T, Error>
+    async fn sync_time(
+        this: &Arc<DuoClientInner>,
+        base_url: &Url,
+        time_sync: &TimeSync,
+    ) -> Result<(), Error> {
+        #[derive(Deserialize, Debug)]
+        struct PingResponse {
+            time: Timestamp,
+        }
+
+        // Ping does not require signing, so it works regardless of the local clock
+        let mut request = DuoRequest::new(
+            base_url.clone(),
+            Method::GET,
+            endpoints::path(&this.api_version, endpoints::PING),
+            Parameters::default(),
+        )
+        .build_no_auth(&this.client)
+        .map_err(Error::unspecified)?;
+        *request.timeout_mut() = this.timeouts.check;
+
+        let response = Self::send_request_json::<PingResponse>(this, request).await?;
+        time_sync.update(response.time);
+
+        Ok(())
+    }
+
+    async fn send_request_json<T>(
+        this: &Arc<DuoClientInner>,
+        mut request: Request,
+    ) -> Result<T, Error>
     where
         T: DeserializeOwned + std::fmt::Debug,
     {
-        let response = client.execute(request).await.map_err(Error::unspecified)?;
+        request
+            .headers_mut()
+            .insert(header::ACCEPT, HeaderValue::from_static("application/json"));
 
-        let body = response
-            .json::<DuoResponse<T>>()
-            .await
-            .map_err(Error::unspecified)?;
+        let response = Self::execute(this, request).await?;
+        if response.status().is_server_error() {
+            return Err(Error::ServiceUnavailable {
+                status: response.status().as_u16(),
+                context: None,
+            });
+        }
+
+        // Intermediaries tend to answer with HTML error pages
+        let is_json = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<mime::Mime>().ok())
+            .map(|m| m.subtype() == mime::JSON || m.suffix() == Some(mime::JSON))
+            .unwrap_or(false);
+        if !is_json {
+            let status = response.status().as_u16();
+            let body = Self::read_body(this, response).await?;
+            let body = String::from_utf8_lossy(&body).into_owned();
+            return Err(Error::UnexpectedResponse {
+                status,
+                body,
+                context: None,
+            });
+        }
+
+        let status = response.status();
+        let body = Self::read_body(this, response).await?;
+        let body: DuoResponse<T> = serde_json::from_slice(&body).map_err(Error::unspecified)?;
+
+        let result = body.ok().map_err(|err| match err {
+            Error::ApiRequestFailed {
+                code,
+                message,
+                message_detail,
+                context,
+            } if status == StatusCode::UNAUTHORIZED || code.code() / 100 == 401 => {
+                Error::InvalidCredentials {
+                    code,
+                    message,
+                    message_detail,
+                    context,
+                }
+            }
+            err => err,
+        });
+
+        // Signature failures are commonly caused by clock drift, resync on next request
+        if let (Err(Error::InvalidCredentials { .. }), Some(time_sync)) = (&result, &this.time_sync)
+        {
+            time_sync.invalidate();
+        }
+
+        result
+    }
+
+    /// Deserialization into the typed response happens straight from the buffered bytes, so
+    /// the size cap bounds peak memory per request
+    async fn read_body(this: &DuoClientInner, mut response: Response) -> Result<Vec<u8>, Error> {
+        let limit = match this.max_response_size {
+            Some(limit) => limit,
+            None => return Ok(response.bytes().await?.to_vec()),
+        };
+
+        if response
+            .content_length()
+            .is_some_and(|len| len > limit as u64)
+        {
+            return Err(Error::ResponseTooLarge {
+                limit,
+                context: None,
+            });
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(Error::ResponseTooLarge {
+                    limit,
+                    context: None,
+                });
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(body)
+    }
+
+    async fn send_request_bytes(
+        this: &Arc<DuoClientInner>,
+        request: Request,
+    ) -> Result<Vec<u8>, Error> {
+        let response = Self::execute(this, request).await?.error_for_status()?;
+        Self::read_body(this, response).await
+    }
+
+    async fn execute(this: &Arc<DuoClientInner>, request: Request) -> Result<Response, Error> {
+        #[cfg(feature = "otel")]
+        let (request, span) = {
+            let mut request = request;
+            let span = otel::start_span(&mut request);
+            (request, span)
+        };
+
+        let response = match &this.on_exchange {
+            Some(hook) => Self::execute_captured(this, hook, request).await,
+            None => Self::send(this, request).await,
+        };
+        #[cfg(feature = "otel")]
+        otel::end_span(&span, &response);
+        let response = response?;
+
+        *this.last_metadata.lock().unwrap() =
+            Some(ResponseMetadata::from_headers(response.headers()));
+
+        // Signed requests must never be followed elsewhere
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+
+            return Err(Error::UnexpectedRedirect {
+                location,
+                context: None,
+            });
+        }
+
+        Ok(response)
+    }
+
+    async fn send(this: &DuoClientInner, request: Request) -> Result<Response, Error> {
+        #[cfg(feature = "tower")]
+        if let Some(transport) = &this.transport {
+            return transport.call(request).await;
+        }
+
+        this.client.execute(request).await.map_err(Error::from_send)
+    }
+
+    async fn execute_captured(
+        this: &Arc<DuoClientInner>,
+        hook: &ExchangeHook,
+        request: Request,
+    ) -> Result<Response, Error> {
+        let captured_request = ExchangeRequest::new(&request);
+
+        let response = match Self::send(this, request).await {
+            Ok(response) => response,
+            Err(err) => {
+                hook(&Exchange {
+                    request: captured_request,
+                    response: None,
+                });
+                return Err(err);
+            }
+        };
+
+        // Buffer the body so it can be both observed and handed back, within the size cap
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let body = Self::read_body(this, response).await?;
+
+        hook(&Exchange {
+            request: captured_request,
+            response: Some(ExchangeResponse::new(status, headers.clone(), &body)),
+        });
+
+        let mut rebuilt = http::Response::new(body);
+        *rebuilt.status_mut() = status;
+        *rebuilt.version_mut() = version;
+        *rebuilt.headers_mut() = headers;
 
-        body.ok()
+        Ok(rebuilt.into())
     }
 }
@@ -0,0 +1,13 @@
+pub const DEFAULT_API_VERSION: &str = "v2";
+
+pub const AUTH: &str = "auth";
+pub const AUTH_STATUS: &str = "auth_status";
+pub const CHECK: &str = "check";
+pub const ENROLL: &str = "enroll";
+pub const ENROLL_STATUS: &str = "enroll_status";
+pub const PING: &str = "ping";
+pub const PREAUTH: &str = "preauth";
+
+pub fn path(api_version: &str, endpoint: &str) -> String {
+    format!("/auth/{}/{}", api_version, endpoint)
+}
@@ -1,25 +1,349 @@
+use std::fmt;
+
 use thiserror::Error;
 
-use super::StdError;
+use super::{types::AuthStatus, StdError};
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Invalid API domain '{domain}': {cause}")]
     InvalidApiDomain { domain: String, cause: StdError },
 
-    #[error("API request failed: {message} ({code})")]
+    #[error("Invalid client configuration: {reason}")]
+    InvalidConfig { reason: String },
+
+    #[error("API request failed: {message} ({}){}", .code.code(), during(.context))]
     ApiRequestFailed {
-        code: u64,
+        code: DuoErrorCode,
         message: String,
         message_detail: Option<String>,
+        context: Option<RequestContext>,
+    },
+
+    #[error(
+        "Duo rejected the request credentials, check the integration key, secret key and \
+         API hostname (and the system clock): {message} ({}){}",
+        .code.code(),
+        during(.context)
+    )]
+    InvalidCredentials {
+        code: DuoErrorCode,
+        message: String,
+        message_detail: Option<String>,
+        context: Option<RequestContext>,
+    },
+
+    /// The URL of the cause names the endpoint
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error(
+        "TLS handshake with Duo failed, check the CA and proxy configuration: {cause}{}",
+        during(.context)
+    )]
+    Tls {
+        cause: reqwest::Error,
+        context: Option<RequestContext>,
+    },
+
+    /// Failure of a custom transport (e.g. a tower timeout layer), other than sending the request
+    #[error("Transport failed: {cause}{}", during(.context))]
+    Transport {
+        cause: StdError,
+        context: Option<RequestContext>,
+    },
+
+    #[error(
+        "Unexpected redirect to {}{}",
+        .location.as_deref().unwrap_or("<unknown>"),
+        during(.context)
+    )]
+    UnexpectedRedirect {
+        location: Option<String>,
+        context: Option<RequestContext>,
+    },
+
+    #[error("SMS passcodes were sent, authenticate with one of them")]
+    PasscodesSent,
+
+    /// Duo answered an SMS factor auth with another status than `sent`, e.g. a lockout
+    #[error("SMS passcodes were not sent: {status_msg}")]
+    PasscodesNotSent {
+        status: AuthStatus,
+        status_msg: String,
+    },
+
+    #[error(
+        "Auth transaction may have been created, check before retrying: {cause}{}",
+        during(.context)
+    )]
+    AuthCreationUncertain {
+        cause: StdError,
+        context: Option<RequestContext>,
+    },
+
+    #[error("Device '{device}' is not known for this user{}", during(.context))]
+    UnknownDevice {
+        device: String,
+        context: Option<RequestContext>,
+    },
+
+    #[error("User has no devices to authenticate with, enroll one first")]
+    NoDevices,
+
+    #[error("User '{username}' already exists{}", during(.context))]
+    UsernameTaken {
+        username: String,
+        context: Option<RequestContext>,
+    },
+
+    /// Origin (scheme, host and port) only, the URL itself carries the activation code
+    #[error("Activation barcode URL ({origin}) is not on the API origin, refusing to fetch it")]
+    UntrustedBarcodeUrl { origin: String },
+
+    #[error("Auth transaction '{txid}' is invalid or has expired{}", during(.context))]
+    InvalidTransaction {
+        txid: String,
+        context: Option<RequestContext>,
+    },
+
+    #[error("Duo is unavailable (HTTP {status}){}", during(.context))]
+    ServiceUnavailable {
+        status: u16,
+        context: Option<RequestContext>,
+    },
+
+    #[error("Response body exceeds {limit} bytes{}", during(.context))]
+    ResponseTooLarge {
+        limit: usize,
+        context: Option<RequestContext>,
+    },
+
+    #[error("Unexpected non-JSON response (HTTP {status}){}", during(.context))]
+    UnexpectedResponse {
+        status: u16,
+        body: String,
+        context: Option<RequestContext>,
+    },
+
+    #[error("Missing required parameter '{0}'")]
+    MissingParameter(&'static str),
+
+    #[error("Parameter '{name}' exceeds {max} bytes")]
+    ParameterTooLong { name: &'static str, max: usize },
+
+    /// Parameter managed by the crate or the signing layer, which an extra parameter can't set
+    #[error("Parameter '{0}' is reserved")]
+    ReservedParameter(String),
+
+    #[error("Client is shutting down")]
+    ShuttingDown,
+
+    #[error("Timed out waiting for the auth decision")]
+    Timeout,
+
+    #[error("Too many auths are being polled concurrently")]
+    Busy,
+
+    #[error(
+        "Response is for transaction '{actual}', expected '{expected}'{}",
+        during(.context)
+    )]
+    ResponseMismatch {
+        expected: String,
+        actual: String,
+        context: Option<RequestContext>,
     },
 
     #[error("Unspecified error")]
     Unspecified(#[from] StdError),
 }
 
+/// Endpoint and (non-sensitive) user of the API request an error was raised by
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestContext {
+    pub endpoint: String,
+    /// `user_id=...` or `username=...`, when the request was for a user
+    pub user: Option<String>,
+}
+
+impl fmt::Display for RequestContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{} for {}", self.endpoint, user),
+            None => f.write_str(&self.endpoint),
+        }
+    }
+}
+
+fn during(context: &Option<RequestContext>) -> String {
+    context
+        .as_ref()
+        .map(|context| format!(", during {}", context))
+        .unwrap_or_default()
+}
+
 impl Error {
+    /// Endpoint and user of the API request that failed. `None` for errors raised before
+    /// anything was sent (e.g. [`Error::ParameterTooLong`]) and for [`Error::Http`], whose cause
+    /// names the URL.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            Self::ApiRequestFailed { context, .. }
+            | Self::InvalidCredentials { context, .. }
+            | Self::Tls { context, .. }
+            | Self::Transport { context, .. }
+            | Self::UnexpectedRedirect { context, .. }
+            | Self::AuthCreationUncertain { context, .. }
+            | Self::UnknownDevice { context, .. }
+            | Self::UsernameTaken { context, .. }
+            | Self::InvalidTransaction { context, .. }
+            | Self::ServiceUnavailable { context, .. }
+            | Self::ResponseTooLarge { context, .. }
+            | Self::UnexpectedResponse { context, .. }
+            | Self::ResponseMismatch { context, .. } => context.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Sets the context of variants carrying one, unless already set
+    pub(crate) fn with_context(mut self, request: &RequestContext) -> Self {
+        if let Self::ApiRequestFailed { context, .. }
+        | Self::InvalidCredentials { context, .. }
+        | Self::Tls { context, .. }
+        | Self::Transport { context, .. }
+        | Self::UnexpectedRedirect { context, .. }
+        | Self::AuthCreationUncertain { context, .. }
+        | Self::UnknownDevice { context, .. }
+        | Self::UsernameTaken { context, .. }
+        | Self::InvalidTransaction { context, .. }
+        | Self::ServiceUnavailable { context, .. }
+        | Self::ResponseTooLarge { context, .. }
+        | Self::UnexpectedResponse { context, .. }
+        | Self::ResponseMismatch { context, .. } = &mut self
+        {
+            context.get_or_insert_with(|| request.clone());
+        }
+        self
+    }
+
+    /// Whether Duo could not be reached or was unavailable, as opposed to answering the request.
+    /// TLS failures don't count, an intercepting proxy must not trigger fallbacks such as
+    /// `preauth_or_stale`.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Self::Http(err) => err.is_connect() || err.is_timeout(),
+            Self::AuthCreationUncertain { .. } | Self::ServiceUnavailable { .. } => true,
+            Self::UnexpectedResponse { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Whether the same request may succeed when retried later: timeouts, connection
+    /// failures, 5xx and rate limiting. Rejections such as invalid credentials or parameters
+    /// aren't, nor is [`Error::AuthCreationUncertain`], which needs checking first.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Self::Http(err) => err.is_connect() || err.is_timeout(),
+            Self::ServiceUnavailable { .. } | Self::Timeout | Self::Busy => true,
+            Self::UnexpectedResponse { status, .. } => *status >= 500 || *status == 429,
+            err => err.duo_code() == Some(DuoErrorCode::RateLimited),
+        }
+    }
+
+    /// Semantic Duo error code, for errors returned by the API
+    pub fn duo_code(&self) -> Option<DuoErrorCode> {
+        match self {
+            Self::ApiRequestFailed { code, .. } | Self::InvalidCredentials { code, .. } => {
+                Some(*code)
+            }
+            _ => None,
+        }
+    }
+
+    /// Tells TLS failures apart from other errors sending a request
+    pub(crate) fn from_send(err: reqwest::Error) -> Self {
+        let mut source = std::error::Error::source(&err);
+        while let Some(cause) = source {
+            if cause.is::<native_tls::Error>() {
+                return Self::Tls {
+                    cause: err,
+                    context: None,
+                };
+            }
+            source = cause.source();
+        }
+
+        Self::Http(err)
+    }
+
     pub(crate) fn unspecified<E: Into<StdError>>(err: E) -> Self {
         Self::Unspecified(err.into())
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DuoErrorCode {
+    MissingParameters,
+    InvalidParameters,
+    MissingCredentials,
+    InvalidIntegrationKey,
+    InvalidSignature,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RateLimited,
+    Unknown(u32),
+}
+
+impl DuoErrorCode {
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            40001 => Self::MissingParameters,
+            40002 => Self::InvalidParameters,
+            40101 => Self::MissingCredentials,
+            40102 => Self::InvalidIntegrationKey,
+            40103 => Self::InvalidSignature,
+            40301 => Self::Forbidden,
+            40401 => Self::NotFound,
+            40501 => Self::MethodNotAllowed,
+            42901 => Self::RateLimited,
+            code => Self::Unknown(code),
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::MissingParameters => 40001,
+            Self::InvalidParameters => 40002,
+            Self::MissingCredentials => 40101,
+            Self::InvalidIntegrationKey => 40102,
+            Self::InvalidSignature => 40103,
+            Self::Forbidden => 40301,
+            Self::NotFound => 40401,
+            Self::MethodNotAllowed => 40501,
+            Self::RateLimited => 42901,
+            Self::Unknown(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for DuoErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::MissingParameters => "missing required request parameters",
+            Self::InvalidParameters => "invalid request parameters",
+            Self::MissingCredentials => "missing request credentials",
+            Self::InvalidIntegrationKey => "invalid integration key in request credentials",
+            Self::InvalidSignature => "invalid signature in request credentials",
+            Self::Forbidden => "access forbidden",
+            Self::NotFound => "resource not found",
+            Self::MethodNotAllowed => "method not allowed",
+            Self::RateLimited => "too many requests",
+            Self::Unknown(_) => "unknown error",
+        };
+
+        write!(f, "{} ({})", description, self.code())
+    }
+}
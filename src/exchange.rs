@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Method, Request, StatusCode, Url,
+};
+
+use serde_json::Value;
+
+use super::request::{redact_serialized, CanonicalRequest, REDACTED};
+
+/// Response fields authenticating a user or activating a device on their own
+const SECRET_FIELDS: [&str; 4] = [
+    "activation_barcode",
+    "activation_code",
+    "activation_url",
+    "trusted_device_token",
+];
+
+pub(crate) type ExchangeHook = Arc<dyn Fn(&Exchange) + Send + Sync>;
+pub(crate) type CanonicalRequestHook = Arc<dyn Fn(&CanonicalRequest) + Send + Sync>;
+
+/// A sanitized HTTP request/response pair, as handed to the `on_exchange` hook. Besides the
+/// `Authorization` header, the values of passcodes, trusted device tokens and activation codes
+/// and URLs read [`REDACTED`] wherever they appear.
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    pub request: ExchangeRequest,
+    /// `None` when no response was received
+    pub response: Option<ExchangeResponse>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExchangeRequest {
+    pub method: Method,
+    /// Secret query parameters are redacted
+    pub url: Url,
+    /// `Authorization` is redacted
+    pub headers: HeaderMap,
+    /// Secret form parameters are redacted
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExchangeResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Secret fields of JSON bodies are redacted, other bodies are kept as received
+    pub body: Vec<u8>,
+}
+
+impl ExchangeRequest {
+    pub(crate) fn new(request: &Request) -> Self {
+        let mut headers = request.headers().clone();
+        if headers.contains_key(header::AUTHORIZATION) {
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static(REDACTED));
+        }
+
+        let mut url = request.url().clone();
+        if let Some(query) = url.query().map(redact_serialized) {
+            url.set_query(Some(&query));
+        }
+
+        // Signed bodies are always form encoded
+        let body = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|body| match std::str::from_utf8(body) {
+                Ok(body) => redact_serialized(body).into_bytes(),
+                Err(_) => body.to_vec(),
+            });
+
+        Self {
+            method: request.method().clone(),
+            url,
+            headers,
+            body,
+        }
+    }
+}
+
+impl ExchangeResponse {
+    pub(crate) fn new(status: StatusCode, headers: HeaderMap, body: &[u8]) -> Self {
+        // Re-serialized only when redacted, other bodies stay byte for byte as received
+        let redacted = serde_json::from_slice::<Value>(body)
+            .ok()
+            .and_then(|mut value| {
+                redact_fields(&mut value).then(|| value.to_string().into_bytes())
+            });
+
+        Self {
+            status,
+            headers,
+            body: redacted.unwrap_or_else(|| body.to_vec()),
+        }
+    }
+}
+
+/// Replaces the values of secret fields at any depth, returning whether there were any
+fn redact_fields(value: &mut Value) -> bool {
+    let mut redacted = false;
+    match value {
+        Value::Object(fields) => {
+            for (k, v) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&k.as_str()) {
+                    *v = Value::String(REDACTED.into());
+                    redacted = true;
+                } else {
+                    redacted |= redact_fields(v);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redacted |= redact_fields(item);
+            }
+        }
+        _ => {}
+    }
+    redacted
+}
@@ -1,9 +1,22 @@
+pub mod builder;
+mod cache;
 pub mod client;
+pub mod endpoints;
 pub mod errors;
+pub mod exchange;
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "poll-loop")]
+mod poll;
 pub mod request;
 pub mod response;
+#[cfg(feature = "tower")]
+mod transport;
 pub mod types;
 
 pub(crate) type StdError = Box<dyn std::error::Error + Send + Sync>;
 
+pub use builder::DuoClientBuilder;
 pub use client::DuoClient;
+#[cfg(feature = "poll-loop")]
+pub use poll::{AuthHandle, PollConfig};
@@ -0,0 +1,79 @@
+use opentelemetry::{
+    global,
+    propagation::Injector,
+    trace::{SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Request, Response,
+};
+
+use super::errors::Error;
+
+struct HeaderInjector<'a>(&'a mut Request);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) else {
+            return;
+        };
+
+        // Never let a propagator touch the signed headers
+        let headers = self.0.headers_mut();
+        if !headers.contains_key(&name) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Starts a client span for `request` as a child of the current context, and injects it with
+/// the globally configured propagator so Duo's leg is parented to it. Only the method, host
+/// and path are recorded, the query and body carry the user and secrets.
+pub(crate) fn start_span(request: &mut Request) -> Context {
+    let tracer = global::tracer("duo-auth");
+    let url = request.url();
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", request.method().to_string()),
+        KeyValue::new("url.path", url.path().to_string()),
+    ];
+    if let Some(host) = url.host_str() {
+        attributes.push(KeyValue::new("server.address", host.to_string()));
+    }
+    if let Some(port) = url.port_or_known_default() {
+        attributes.push(KeyValue::new("server.port", i64::from(port)));
+    }
+
+    let span = tracer
+        .span_builder(format!("{} {}", request.method(), url.path()))
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start(&tracer);
+    let cx = Context::current_with_span(span);
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut HeaderInjector(request))
+    });
+    cx
+}
+
+pub(crate) fn end_span(cx: &Context, result: &Result<Response, Error>) {
+    let span = cx.span();
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                i64::from(status.as_u16()),
+            ));
+            if status.is_client_error() || status.is_server_error() {
+                span.set_status(Status::error(status.to_string()));
+            }
+        }
+        Err(err) => span.set_status(Status::error(err.to_string())),
+    }
+    span.end();
+}
@@ -0,0 +1,867 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::{
+    stream::{self, FuturesUnordered},
+    Stream, StreamExt,
+};
+use tokio::{
+    sync::{oneshot, OwnedSemaphorePermit, TryAcquireError},
+    task::JoinHandle,
+};
+
+use super::{
+    client::{DuoClient, DuoClientInner},
+    errors::Error,
+    types::{
+        AuthDecision, AuthOutcome, AuthRequest, AuthRequestFactor, AuthStats, AuthStatus,
+        AuthStatusResponse, Device, DeviceCapability, EnrollResponse, EnrollStatusResponse,
+        FailMode, PreauthRequest, PreauthResponse, PreauthResult, ReauthOutcome, SavedAuth,
+        TimedAuthStatus, Txid, User,
+    },
+    StdError,
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How the auth and enroll loops poll, set on the client with
+/// `DuoClientBuilder::with_poll_config` and overridable per call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PollConfig {
+    /// Delay between polls, defaults to 2 seconds. Duo gives no re-poll hint; as
+    /// `auth_status` long-polls until the status changes, time spent waiting on it counts
+    /// towards the interval.
+    pub interval: Duration,
+    /// Overall time to wait before failing with [`Error::Timeout`], unlimited by default
+    pub timeout: Option<Duration>,
+    /// Time to wait for the status to change before failing with [`Error::Timeout`], unlimited
+    /// by default. An enrollment only changes status once activated, so this bounds its wait
+    /// like `timeout`.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_POLL_INTERVAL,
+            timeout: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+impl PollConfig {
+    /// Fails with [`Error::InvalidConfig`] unless the interval is non-zero and shorter than
+    /// the timeout, so Duo is never polled in a busy loop
+    pub fn validate(&self) -> Result<(), Error> {
+        let reason = match self.timeout {
+            _ if self.interval.is_zero() => "poll interval must not be zero".to_string(),
+            Some(timeout) if self.interval >= timeout => format!(
+                "poll interval ({:?}) must be shorter than the poll timeout ({:?})",
+                self.interval, timeout
+            ),
+            _ => return Ok(()),
+        };
+
+        Err(Error::InvalidConfig { reason })
+    }
+
+    /// Deadline of a loop started at `started` whose status last changed at `progressed`,
+    /// whichever of the overall and idle one comes first
+    fn deadline(&self, started: Instant, progressed: Instant) -> Option<Instant> {
+        let overall = self.timeout.map(|timeout| started + timeout);
+        let idle = self.idle_timeout.map(|idle| progressed + idle);
+        match (overall, idle) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct OutcomeCounters {
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    locked_out: AtomicU64,
+    timed_out: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl OutcomeCounters {
+    /// Counts the outcome if the client counts outcomes at all
+    fn record(this: &DuoClientInner, result: &Result<AuthOutcome, Error>) {
+        let Some(counters) = &this.outcomes else {
+            return;
+        };
+        counters.count(result);
+    }
+
+    fn count(&self, result: &Result<AuthOutcome, Error>) {
+        let counter = match result {
+            Ok(outcome) => match (outcome.decision, outcome.status) {
+                (AuthDecision::Allow, _) => &self.allowed,
+                (AuthDecision::PasscodesSent, _) => return,
+                (AuthDecision::Deny, AuthStatus::LockedOut) => &self.locked_out,
+                (AuthDecision::NoAnswer, _) => &self.timed_out,
+                (AuthDecision::Deny, _) => &self.denied,
+            },
+            Err(Error::Timeout) => &self.timed_out,
+            Err(_) => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> AuthStats {
+        AuthStats {
+            allowed: self.allowed.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            locked_out: self.locked_out.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Background auth started with [`DuoClient::spawn_auth`], dropping it cancels the polling
+pub struct AuthHandle {
+    task: JoinHandle<()>,
+}
+
+impl AuthHandle {
+    /// Same as dropping the handle, the receiver then resolves to a `RecvError`
+    pub fn cancel(self) {}
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Drop for AuthHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl DuoClient {
+    /// Outcome counts of every auth polled to a decision by this client so far, including
+    /// failed ones. Transactions only streamed with `auth_status_stream` aren't counted. All
+    /// zero unless enabled with `count_outcomes`.
+    pub fn stats(&self) -> AuthStats {
+        self.0
+            .outcomes
+            .as_ref()
+            .map(OutcomeCounters::snapshot)
+            .unwrap_or_default()
+    }
+
+    pub fn auth_wait(&self, data: AuthRequest) -> impl Future<Output = Result<bool, StdError>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let allowed = Self::auth_decision(this, data, |_| {}).await?;
+            Ok(allowed)
+        }
+    }
+
+    /// Like [`DuoClient::auth_wait`], invoking `on_status` whenever the transaction status changes
+    pub fn auth_with_progress<F>(
+        &self,
+        data: AuthRequest,
+        on_status: F,
+    ) -> impl Future<Output = Result<bool, Error>>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let this = Arc::clone(&self.0);
+
+        async move { Self::auth_decision(this, data, on_status).await }
+    }
+
+    /// Like [`DuoClient::auth_with_progress`], returning the final status and poll statistics.
+    /// `elapsed` is measured from the creation of the transaction.
+    pub fn auth_outcome<F>(
+        &self,
+        data: AuthRequest,
+        on_status: F,
+    ) -> impl Future<Output = Result<AuthOutcome, Error>>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let this = Arc::clone(&self.0);
+
+        async move { Self::auth_then_wait(this, data, on_status).await }
+    }
+
+    /// Like [`DuoClient::auth_outcome`], failing with [`Error::Timeout`] once the status hasn't
+    /// changed for `idle_timeout`, so e.g. a phone auth is waited on for as long as the call
+    /// progresses. `max` bounds the whole wait, a flapping status can't extend it forever.
+    pub fn auth_with_idle_timeout<F>(
+        &self,
+        data: AuthRequest,
+        idle_timeout: Duration,
+        max: Duration,
+        on_status: F,
+    ) -> impl Future<Output = Result<AuthOutcome, Error>>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let config = PollConfig {
+                timeout: Some(max),
+                idle_timeout: Some(idle_timeout),
+                ..this.poll
+            };
+            config.validate()?;
+            Self::auth_then_wait_with(this, data, config, on_status).await
+        }
+    }
+
+    /// Like [`DuoClient::auth_outcome`], polling per `config` instead of the client's own
+    /// config. An invalid `config` fails with [`Error::InvalidConfig`] before the auth is sent.
+    pub fn auth_with_poll_config<F>(
+        &self,
+        data: AuthRequest,
+        config: PollConfig,
+        on_status: F,
+    ) -> impl Future<Output = Result<AuthOutcome, Error>>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            config.validate()?;
+            Self::auth_then_wait_with(this, data, config, on_status).await
+        }
+    }
+
+    /// Batteries-included push login for `username`:
+    ///
+    /// - preauth allow resolves to `true`, deny and enrollment required to `false`
+    /// - pushes to the first push capable device, showing the username in the prompt, or lets
+    ///   Duo pick a factor when there is none
+    /// - fails with [`Error::NoDevices`] when preauth requires an auth but the user has no
+    ///   devices, e.g. when only partially provisioned
+    /// - gives up with [`Error::Timeout`] after 60 seconds
+    pub fn login<U: Into<String>>(&self, username: U) -> impl Future<Output = Result<bool, Error>> {
+        let this = Arc::clone(&self.0);
+        let username = username.into();
+
+        async move {
+            let user = User::username(username.clone());
+            let choose = move |devices: &[Device]| match Device::recommend(
+                devices,
+                &[DeviceCapability::Push],
+            ) {
+                Some((device, _)) => AuthRequestFactor::Push {
+                    device: device.device.clone(),
+                    r#type: None,
+                    display_username: Some(username),
+                    push_info: None,
+                },
+                None => AuthRequestFactor::auto(),
+            };
+
+            Self::preauth_then_auth(this, user, choose, Some(LOGIN_TIMEOUT)).await
+        }
+    }
+
+    /// Runs preauth and, unless it already decided like with [`DuoClient::login`], lets
+    /// `choose` pick the factor and device from the user's devices to authenticate with. The
+    /// poll timeout bounds the whole flow. `choose` is never called with no devices, that fails
+    /// with [`Error::NoDevices`] instead.
+    pub fn interactive_auth<F>(
+        &self,
+        user: User,
+        choose: F,
+    ) -> impl Future<Output = Result<bool, Error>>
+    where
+        F: FnOnce(&[Device]) -> AuthRequestFactor,
+    {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let timeout = this.poll.timeout;
+            Self::preauth_then_auth(this, user, choose, timeout).await
+        }
+    }
+
+    /// Verifies a passcode, e.g. one read from Duo Mobile on a `mobile_otp` capable device
+    pub fn auth_passcode<P: Into<String>>(
+        &self,
+        user: User,
+        passcode: P,
+    ) -> impl Future<Output = Result<bool, Error>> {
+        let this = Arc::clone(&self.0);
+        let data = AuthRequest::new(user, AuthRequestFactor::passcode(passcode));
+
+        async move { Self::auth_decision(this, data, |_| {}).await }
+    }
+
+    /// Verifies `passcode` when the user typed one, pushes to their first push capable device
+    /// otherwise
+    pub fn auth_passcode_or_push(
+        &self,
+        user: User,
+        passcode: Option<String>,
+    ) -> impl Future<Output = Result<bool, Error>> {
+        let this = Arc::clone(&self.0);
+        let factor = match passcode {
+            Some(passcode) => AuthRequestFactor::passcode(passcode),
+            None => AuthRequestFactor::push("auto"),
+        };
+
+        async move { Self::auth_decision(this, AuthRequest::new(user, factor), |_| {}).await }
+    }
+
+    /// Reauthenticates silently while Duo still accepts the trusted device token, falling back
+    /// to a full (automatic factor) auth when it's expired or rejected
+    pub fn reauth_with_token<T: Into<String>>(
+        &self,
+        user: User,
+        token: T,
+    ) -> impl Future<Output = Result<ReauthOutcome, Error>> {
+        let this = Arc::clone(&self.0);
+        let token = token.into();
+
+        async move {
+            let mut preauth = PreauthRequest::new(user.clone());
+            preauth.trusted_device_token = Some(token.clone());
+
+            let allowed = match Self::request_preauth(this.clone(), preauth).await?.result() {
+                PreauthResult::Allow => true,
+                PreauthResult::Deny | PreauthResult::Enroll => false,
+                PreauthResult::Auth => {
+                    let data = AuthRequest::new(user, AuthRequestFactor::auto());
+                    let outcome = Self::auth_then_wait(this, data, |_| {}).await?;
+                    if outcome.decision == AuthDecision::PasscodesSent {
+                        return Err(Error::PasscodesSent);
+                    }
+
+                    return Ok(ReauthOutcome {
+                        allowed: outcome.allowed(),
+                        silent: false,
+                        trusted_device_token: outcome.trusted_device_token,
+                    });
+                }
+            };
+
+            Ok(ReauthOutcome {
+                allowed,
+                silent: true,
+                trusted_device_token: allowed.then_some(token),
+            })
+        }
+    }
+
+    /// Enrolls a user and waits for them to activate Duo Mobile, resolving to `true` once
+    /// activated and `false` when Duo finds the activation code invalid. `on_enrollment` gets
+    /// the activation code and barcode to display; the activation expires along with `timeout`,
+    /// after which this fails with [`Error::Timeout`].
+    pub fn enroll_and_wait<U, F>(
+        &self,
+        username: Option<U>,
+        timeout: Duration,
+        on_enrollment: F,
+    ) -> impl Future<Output = Result<bool, Error>>
+    where
+        U: Into<String>,
+        F: FnOnce(&EnrollResponse),
+    {
+        let config = PollConfig {
+            timeout: Some(timeout),
+            ..self.0.poll
+        };
+        self.enroll_with_poll_config(username, config, on_enrollment)
+    }
+
+    /// Like [`DuoClient::enroll_and_wait`], polling per `config` instead of the client's own
+    /// config. The activation expires along with `config.timeout`, if any. An invalid `config`
+    /// fails with [`Error::InvalidConfig`] before the user is enrolled.
+    pub fn enroll_with_poll_config<U, F>(
+        &self,
+        username: Option<U>,
+        config: PollConfig,
+        on_enrollment: F,
+    ) -> impl Future<Output = Result<bool, Error>>
+    where
+        U: Into<String>,
+        F: FnOnce(&EnrollResponse),
+    {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            config.validate()?;
+            let started = Instant::now();
+            let deadline = config.deadline(started, started);
+            let valid_secs = config.timeout.map(|timeout| timeout.as_secs().max(1));
+            let enrollment = Self::request_enroll(this.clone(), username, valid_secs).await?;
+            on_enrollment(&enrollment);
+
+            loop {
+                let poll_started = Instant::now();
+                let status = Self::request_enroll_status(
+                    this.clone(),
+                    enrollment.user_id.as_str(),
+                    enrollment.activation_code.as_str(),
+                );
+
+                match until(deadline, status).await? {
+                    EnrollStatusResponse::Success => return Ok(true),
+                    EnrollStatusResponse::Invalid => return Ok(false),
+                    EnrollStatusResponse::Waiting => {
+                        Self::wait_poll_interval(&this, config.interval, poll_started, deadline)
+                            .await?
+                    }
+                }
+            }
+        }
+    }
+
+    /// Polls a transaction started with [`DuoClient::start_auth`], possibly by another process.
+    /// `elapsed` is measured from the resumption.
+    pub fn resume_poll<F>(
+        &self,
+        saved: SavedAuth,
+        on_status: F,
+    ) -> impl Future<Output = Result<AuthOutcome, Error>>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let _slot = Self::acquire_poll_slot(&this).await?;
+            Self::wait_auth_outcome(this, &saved.txid, on_status).await
+        }
+    }
+
+    /// Polls the transaction, yielding every status change (e.g. `Calling` then `Answered` for
+    /// phone callbacks). The stream ends after the status carrying the final decision.
+    pub fn auth_status_stream(&self, txid: Txid) -> impl Stream<Item = Result<AuthStatus, Error>> {
+        self.auth_status_timed_stream(txid)
+            .map(|result| result.map(|timed| timed.status))
+    }
+
+    /// Like [`DuoClient::auth_status_stream`], along with how long each status took to arrive,
+    /// e.g. to notice a degrading Duo while auths still succeed
+    pub fn auth_status_timed_stream(
+        &self,
+        txid: Txid,
+    ) -> impl Stream<Item = Result<TimedAuthStatus, Error>> {
+        let this = Arc::clone(&self.0);
+        let started = Instant::now();
+        let deadline = this.poll.timeout.map(|timeout| started + timeout);
+        let state = Some((txid, None::<AuthStatus>, None::<Instant>, None));
+
+        stream::unfold(state, move |state| {
+            let this = Arc::clone(&this);
+
+            async move {
+                let (txid, mut last_status, mut last_poll, mut slot) = state?;
+
+                loop {
+                    if last_poll.is_none() {
+                        slot = match Self::acquire_poll_slot(&this).await {
+                            Ok(slot) => slot,
+                            Err(err) => return Some((Err(err), None)),
+                        };
+                    }
+
+                    if let Some(poll_started) = last_poll {
+                        if let Err(err) = Self::wait_poll_interval(
+                            &this,
+                            this.poll.interval,
+                            poll_started,
+                            deadline,
+                        )
+                        .await
+                        {
+                            return Some((Err(err), None));
+                        }
+                    }
+
+                    let poll_started = Instant::now();
+                    last_poll = Some(poll_started);
+                    let response = match Self::poll_status(&this, &txid, deadline).await {
+                        Ok(response) => response,
+                        Err(err) => return Some((Err(err), None)),
+                    };
+                    let timed = TimedAuthStatus {
+                        status: response.status,
+                        latency: poll_started.elapsed(),
+                        elapsed: started.elapsed(),
+                    };
+
+                    if response.decision().is_some() {
+                        return Some((Ok(timed), None));
+                    }
+                    if last_status != Some(response.status) {
+                        last_status = Some(response.status);
+                        let state = (txid, last_status, last_poll, slot);
+                        return Some((Ok(timed), Some(state)));
+                    }
+                }
+            }
+        })
+    }
+
+    /// Like [`DuoClient::auth_with_progress`], polling on a spawned task and delivering the
+    /// decision over the returned channel. Must be called within a Tokio runtime.
+    pub fn spawn_auth(
+        &self,
+        data: AuthRequest,
+    ) -> (AuthHandle, oneshot::Receiver<Result<bool, Error>>) {
+        let (sender, receiver) = oneshot::channel();
+        let auth = self.auth_with_progress(data, |_| {});
+
+        let task = tokio::spawn(async move {
+            let _ = sender.send(auth.await);
+        });
+
+        (AuthHandle { task }, receiver)
+    }
+
+    /// Pushes to every device concurrently and resolves on the first approval, abandoning and
+    /// no longer polling the other transactions. A denial on one device keeps waiting on the
+    /// others; the auth is only denied once every device denied. If a device fails instead and
+    /// none approves, the last error is returned. The poll timeout is shared by all devices,
+    /// failing with [`Error::Timeout`] once reached. The whole call counts as one outcome in
+    /// [`DuoClient::stats`], and fails with [`Error::NoDevices`] without any device.
+    pub fn auth_any_device(
+        &self,
+        user: User,
+        devices: Vec<String>,
+    ) -> impl Future<Output = Result<bool, Error>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            if devices.is_empty() {
+                return Err(Error::NoDevices);
+            }
+
+            // One slot for the whole login, the devices are pushed concurrently
+            let _slot = Self::acquire_poll_slot(&this).await?;
+            let config = this.poll;
+            let deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+            let mut pending = devices
+                .into_iter()
+                .map(|device| {
+                    let this = Arc::clone(&this);
+                    let data = AuthRequest::new(user.clone(), AuthRequestFactor::push(device));
+
+                    async move {
+                        let txid = Self::request_auth(this.clone(), data).await?;
+                        Self::poll_auth_outcome(this, &txid, config, |_| {}).await
+                    }
+                })
+                .collect::<FuturesUnordered<_>>();
+
+            let decide = async move {
+                let mut denied = None;
+                let mut failed = None;
+                while let Some(result) = pending.next().await {
+                    match result {
+                        Ok(outcome) if outcome.allowed() => return Ok(outcome),
+                        Ok(outcome) => denied = Some(outcome),
+                        Err(err) => failed = Some(err),
+                    }
+                }
+
+                match (failed, denied) {
+                    (Some(err), _) => Err(err),
+                    (None, Some(outcome)) => Ok(outcome),
+                    (None, None) => unreachable!("there is at least one device"),
+                }
+            };
+
+            // One deadline for the whole set, dropping it cancels every outstanding poll
+            let result = until(deadline, decide).await;
+            OutcomeCounters::record(&this, &result);
+            allowed(result?)
+        }
+    }
+
+    /// Like [`DuoClient::auth_wait`], resolving per `fail_mode` when Duo can't be connected to
+    /// for creating the auth, i.e. nothing was sent. Once the auth may have been created, errors
+    /// are returned as is, as a push may still be pending: a 5xx or timeout answering the auth
+    /// ([`Error::AuthCreationUncertain`]) and any failure while polling. Denials are never
+    /// affected.
+    pub fn authenticate_with_failmode(
+        &self,
+        data: AuthRequest,
+        fail_mode: FailMode,
+    ) -> impl Future<Output = Result<bool, Error>> {
+        let this = Arc::clone(&self.0);
+
+        async move {
+            let _slot = Self::acquire_poll_slot(&this).await?;
+            let txid = match Self::request_auth(this.clone(), data).await {
+                Ok(txid) => txid,
+                Err(Error::Http(err)) if err.is_connect() => return Ok(fail_mode == FailMode::Safe),
+                Err(err) => return Err(err),
+            };
+
+            allowed(Self::wait_auth_outcome(this, &txid, |_| {}).await?)
+        }
+    }
+
+    async fn preauth_then_auth<F>(
+        this: Arc<DuoClientInner>,
+        user: User,
+        choose: F,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Error>
+    where
+        F: FnOnce(&[Device]) -> AuthRequestFactor,
+    {
+        let flow = async move {
+            let preauth = PreauthRequest::new(user.clone());
+            let devices = match Self::request_preauth(this.clone(), preauth).await? {
+                PreauthResponse::Auth { devices, .. } if devices.is_empty() => {
+                    return Err(Error::NoDevices)
+                }
+                PreauthResponse::Auth { devices, .. } => devices,
+                PreauthResponse::Allow { .. } => return Ok(true),
+                PreauthResponse::Deny { .. } | PreauthResponse::Enroll { .. } => return Ok(false),
+            };
+
+            let factor = choose(&devices);
+            Self::auth_decision(this, AuthRequest::new(user, factor), |_| {}).await
+        };
+
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, flow)
+                .await
+                .map_err(|_| Error::Timeout)?,
+            None => flow.await,
+        }
+    }
+
+    async fn auth_decision<F>(
+        this: Arc<DuoClientInner>,
+        data: AuthRequest,
+        on_status: F,
+    ) -> Result<bool, Error>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        allowed(Self::auth_then_wait(this, data, on_status).await?)
+    }
+
+    /// Starts the auth once a poll loop slot is free, so queued auths don't prompt users
+    /// before they're polled
+    async fn auth_then_wait<F>(
+        this: Arc<DuoClientInner>,
+        data: AuthRequest,
+        on_status: F,
+    ) -> Result<AuthOutcome, Error>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let config = this.poll;
+        Self::auth_then_wait_with(this, data, config, on_status).await
+    }
+
+    async fn auth_then_wait_with<F>(
+        this: Arc<DuoClientInner>,
+        data: AuthRequest,
+        config: PollConfig,
+        on_status: F,
+    ) -> Result<AuthOutcome, Error>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let _slot = Self::acquire_poll_slot(&this).await?;
+        let txid = Self::request_auth(this.clone(), data).await?;
+        Self::wait_auth_outcome_within(this, &txid, config, on_status).await
+    }
+
+    /// Claims a slot of the client's concurrent poll loop cap, if any, for as long as the
+    /// permit is held
+    async fn acquire_poll_slot(
+        this: &DuoClientInner,
+    ) -> Result<Option<OwnedSemaphorePermit>, Error> {
+        let slots = match &this.poll_slots {
+            Some(slots) => Arc::clone(slots),
+            None => return Ok(None),
+        };
+
+        if this.fail_when_busy {
+            return match slots.try_acquire_owned() {
+                Ok(permit) => Ok(Some(permit)),
+                Err(TryAcquireError::NoPermits) => Err(Error::Busy),
+                Err(TryAcquireError::Closed) => unreachable!("poll slots are never closed"),
+            };
+        }
+
+        let shutdown = this.shutdown.notified();
+        if this.shutdown.is_requested() {
+            return Err(Error::ShuttingDown);
+        }
+
+        tokio::select! {
+            permit = slots.acquire_owned() => {
+                Ok(Some(permit.expect("poll slots are never closed")))
+            }
+            _ = shutdown => Err(Error::ShuttingDown),
+        }
+    }
+
+    async fn wait_auth_outcome<F>(
+        this: Arc<DuoClientInner>,
+        tx_id: &Txid,
+        on_status: F,
+    ) -> Result<AuthOutcome, Error>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let config = this.poll;
+        Self::wait_auth_outcome_within(this, tx_id, config, on_status).await
+    }
+
+    async fn wait_auth_outcome_within<F>(
+        this: Arc<DuoClientInner>,
+        tx_id: &Txid,
+        config: PollConfig,
+        on_status: F,
+    ) -> Result<AuthOutcome, Error>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let result = Self::poll_auth_outcome(this.clone(), tx_id, config, on_status).await;
+        OutcomeCounters::record(&this, &result);
+        result
+    }
+
+    async fn poll_auth_outcome<F>(
+        this: Arc<DuoClientInner>,
+        tx_id: &Txid,
+        config: PollConfig,
+        mut on_status: F,
+    ) -> Result<AuthOutcome, Error>
+    where
+        F: FnMut(&AuthStatus),
+    {
+        let started = Instant::now();
+        let mut progressed = started;
+        let mut polls = 0;
+        let mut last_status: Option<AuthStatus> = None;
+
+        // The idle deadline moves along with status changes
+        let deadline = |progressed: Instant| config.deadline(started, progressed);
+
+        loop {
+            let poll_started = Instant::now();
+            let response = Self::poll_status(&this, tx_id, deadline(progressed)).await?;
+            polls += 1;
+            if last_status != Some(response.status) {
+                on_status(&response.status);
+                last_status = Some(response.status);
+                progressed = Instant::now();
+            }
+
+            match response.decision() {
+                None => {
+                    let deadline = deadline(progressed);
+                    Self::wait_poll_interval(&this, config.interval, poll_started, deadline).await?
+                }
+                Some(decision) => {
+                    return Ok(AuthOutcome {
+                        decision,
+                        status: response.status,
+                        polls,
+                        elapsed: started.elapsed(),
+                        trusted_device_token: response.trusted_device_token,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Fetches the transaction status, giving up at the deadline. `auth_status` long-polls
+    /// until the status changes, so a single poll timing out (see [`Timeouts::auth_status`])
+    /// only means the user hasn't decided yet and is polled again.
+    ///
+    /// [`Timeouts::auth_status`]: crate::builder::Timeouts::auth_status
+    async fn poll_status(
+        this: &Arc<DuoClientInner>,
+        tx_id: &Txid,
+        deadline: Option<Instant>,
+    ) -> Result<AuthStatusResponse, Error> {
+        loop {
+            match until(deadline, Self::request_auth_status(this.clone(), tx_id)).await {
+                Err(Error::Http(err)) if err.is_timeout() => {
+                    if this.shutdown.is_requested() {
+                        return Err(Error::ShuttingDown);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn wait_poll_interval(
+        this: &DuoClientInner,
+        interval: Duration,
+        poll_started: Instant,
+        deadline: Option<Instant>,
+    ) -> Result<(), Error> {
+        let shutdown = this.shutdown.notified();
+        if this.shutdown.is_requested() {
+            return Err(Error::ShuttingDown);
+        }
+
+        // auth_status long-polls until the status changes, so only wait out
+        // whatever is left of the interval instead of sleeping it in full
+        let next_poll = poll_started + interval;
+
+        // Don't overshoot the deadline when it falls within the interval
+        let (wake, timed_out) = match deadline {
+            Some(deadline) if deadline <= next_poll => (deadline, true),
+            _ => (next_poll, false),
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(wake.into()) => {
+                if timed_out {
+                    Err(Error::Timeout)
+                } else {
+                    Ok(())
+                }
+            }
+            _ = shutdown => Err(Error::ShuttingDown),
+        }
+    }
+}
+
+/// Whether the user approved, for flows asking for a yes or no
+fn allowed(outcome: AuthOutcome) -> Result<bool, Error> {
+    match outcome.decision {
+        AuthDecision::Allow => Ok(true),
+        AuthDecision::Deny | AuthDecision::NoAnswer => Ok(false),
+        AuthDecision::PasscodesSent => Err(Error::PasscodesSent),
+    }
+}
+
+/// Runs `request`, failing with [`Error::Timeout`] once the deadline passes
+async fn until<T, F>(deadline: Option<Instant>, request: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), request)
+            .await
+            .map_err(|_| Error::Timeout)?,
+        None => request.await,
+    }
+}
@@ -1,13 +1,24 @@
 use std::collections::BTreeMap;
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
-use reqwest::{Client, Method, Request, Url};
+use reqwest::{header::HeaderValue, Client, Method, Request, Url};
 use sha1::Sha1;
+use sha2::Sha512;
 
-use super::StdError;
+use super::{errors::Error, StdError};
 
-#[derive(Default)]
+/// Names the signing layer sends as headers, rejected as parameters
+const RESERVED: [&str; 4] = ["authorization", "content-type", "date", "host"];
+
+/// Parameters authenticating on their own, whose values [`CanonicalRequest`] never carries
+const SECRETS: [&str; 3] = ["activation_code", "passcode", "trusted_device_token"];
+
+/// Stands in for the values of secret parameters
+pub const REDACTED: &str = "[redacted]";
+
+#[derive(Clone, Debug, Default)]
 pub struct Parameters(BTreeMap<String, String>);
 
 impl Parameters {
@@ -21,6 +32,55 @@ impl Parameters {
         }
     }
 
+    /// Like `set`, rejecting names reserved for the signed headers with
+    /// [`Error::ReservedParameter`]
+    pub fn try_set<K: Into<String>, V: Into<String>>(&mut self, k: K, v: V) -> Result<(), Error> {
+        let k = k.into();
+        if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(&k)) {
+            return Err(Error::ReservedParameter(k));
+        }
+
+        self.set(k, v);
+        Ok(())
+    }
+
+    pub fn get(&self, k: &str) -> Option<&str> {
+        self.0.get(k).map(String::as_str)
+    }
+
+    pub fn remove(&mut self, k: &str) -> Option<String> {
+        self.0.remove(k)
+    }
+
+    /// Adds all of `other`, replacing values already set under the same key
+    pub fn merge(&mut self, other: Parameters) {
+        self.0.extend(other.0);
+    }
+
+    /// Adds caller supplied parameters, rejecting reserved names and names already set by the
+    /// crate instead of replacing them
+    pub(crate) fn merge_extra(&mut self, other: Parameters) -> Result<(), Error> {
+        if let Some(k) = other.0.keys().find(|k| self.0.contains_key(*k)) {
+            return Err(Error::ReservedParameter(k.clone()));
+        }
+        for (k, v) in other.0 {
+            self.try_set(k, v)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy with the values of secret parameters (e.g. `passcode`) replaced by [`REDACTED`]
+    pub(crate) fn redacted(&self) -> Parameters {
+        let mut redacted = self.clone();
+        for (k, v) in redacted.0.iter_mut() {
+            if SECRETS.contains(&k.as_str()) {
+                *v = REDACTED.into();
+            }
+        }
+        redacted
+    }
+
     pub fn serialize(&self) -> String {
         self.0
             .iter()
@@ -30,18 +90,77 @@ impl Parameters {
     }
 }
 
+/// Serialized parameters (a query or form body) with the values of secret parameters replaced
+/// by [`REDACTED`], keeping the encoding of the others as is
+pub(crate) fn redact_serialized(serialized: &str) -> String {
+    serialized
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((k, _)) if SECRETS.contains(&k) => {
+                format!("{}={}", k, urlencoding::encode(REDACTED))
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Parameters {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut parameters = Self::default();
+        parameters.extend(iter);
+        parameters
+    }
+}
+
+/// Later values replace earlier ones under the same key, like `set`
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for Parameters {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.set(k, v);
+        }
+    }
+}
+
 impl From<Parameters> for BTreeMap<String, String> {
     fn from(value: Parameters) -> Self {
         value.0
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    #[default]
+    HmacSha1,
+    HmacSha512,
+}
+
+/// How parameters of requests with a body are sent. Auth API v2 documents form encoding only,
+/// signed over the same string as the query of other requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BodyEncoding {
+    /// `application/x-www-form-urlencoded`
+    #[default]
+    Form,
+}
+
+impl BodyEncoding {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Form => "application/x-www-form-urlencoded",
+        }
+    }
+}
+
 pub struct DuoRequest {
     url: Url,
     method: Method,
     path: String,
     date: DateTime<Utc>,
     parameters: Parameters,
+    algorithm: SignatureAlgorithm,
+    body_encoding: BodyEncoding,
 }
 
 impl DuoRequest {
@@ -52,78 +171,183 @@ impl DuoRequest {
             path: path.into(),
             date: Utc::now(),
             parameters,
+            algorithm: SignatureAlgorithm::default(),
+            body_encoding: BodyEncoding::default(),
         }
     }
 
-    pub fn build(&self, client: &Client, ikey: &str, skey: &str) -> Result<Request, StdError> {
-        let no_body = matches!(self.method, Method::GET | Method::HEAD);
+    pub fn with_signature_algorithm(mut self, algorithm: SignatureAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn with_body_encoding(mut self, encoding: BodyEncoding) -> Self {
+        self.body_encoding = encoding;
+        self
+    }
+
+    pub fn with_date(mut self, date: DateTime<Utc>) -> Self {
+        self.date = date;
+        self
+    }
 
+    pub fn build(&self, client: &Client, ikey: &str, skey: &str) -> Result<Request, StdError> {
         let parameters_str = self.parameters.serialize();
-        let mut url = self.url.clone();
-        url.set_path(&self.path);
-        if no_body {
-            url.set_query(Some(&parameters_str))
-        }
+        let url = self.request_url(&parameters_str);
 
         let signature = self.build_signature(skey, &parameters_str)?;
+        let mut authorization = HeaderValue::try_from(authorization_header(ikey, &signature))?;
+        authorization.set_sensitive(true);
+
         let mut rb = client
             .request(self.method.clone(), url)
-            .basic_auth(ikey.clone(), Some(signature))
-            .header("Date", self.date.to_rfc2822());
+            .header("Authorization", authorization)
+            .header("Date", self.formatted_date());
 
-        if !no_body {
+        if let Some(body) = self.body(&parameters_str) {
             rb = rb
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .body(parameters_str)
+                .header("Content-Type", self.body_encoding.content_type())
+                .body(body)
         }
 
         rb.build().map_err(|e| e.into())
     }
 
     pub fn build_no_auth(&self, client: &Client) -> Result<Request, StdError> {
-        let no_body = matches!(self.method, Method::GET | Method::HEAD);
-
         let parameters_str = self.parameters.serialize();
-        let mut url = self.url.clone();
-        url.set_path(&self.path);
-        if no_body {
-            url.set_query(Some(&parameters_str))
-        }
+        let url = self.request_url(&parameters_str);
 
         let mut rb = client
             .request(self.method.clone(), url)
-            .header("Date", self.date.to_rfc2822())
+            .header("Date", self.formatted_date())
             .header(
                 "User-Agent",
                 concat!("duo-auth-rs/", env!("CARGO_PKG_VERSION")),
             );
 
-        if !no_body {
+        if let Some(body) = self.body(&parameters_str) {
             rb = rb
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .body(parameters_str)
+                .header("Content-Type", self.body_encoding.content_type())
+                .body(body)
         }
 
         rb.build().map_err(|e| e.into())
     }
 
-    fn build_signature(&self, skey: &str, parameters_str: &str) -> Result<String, StdError> {
+    /// What gets signed and sent, e.g. for audit logging. Credentials are left out, and the
+    /// values of passcodes, trusted device tokens and activation codes read [`REDACTED`].
+    pub fn canonical(&self) -> CanonicalRequest {
+        let parameters = self.parameters.redacted();
+        let parameters_str = parameters.serialize();
+
+        CanonicalRequest {
+            method: self.method.clone(),
+            url: self.request_url(&parameters_str),
+            parameters: parameters.into(),
+            date: self.formatted_date(),
+            canonical_string: self.canonical_string(&parameters_str),
+        }
+    }
+
+    /// Exact body that is sent and signed, `None` for methods carrying parameters in the query
+    pub fn encoded_body(&self) -> Option<String> {
+        self.body(&self.parameters.serialize())
+    }
+
+    pub fn debug_signature(&self, skey: &str) -> Result<SignatureDebug, StdError> {
+        let parameters_str = self.parameters.serialize();
+
+        Ok(SignatureDebug {
+            canonical_string: self.canonical_string(&parameters_str),
+            date: self.formatted_date(),
+            signature: self.build_signature(skey, &parameters_str)?,
+        })
+    }
+
+    fn request_url(&self, parameters_str: &str) -> Url {
+        let mut url = self.url.clone();
+        url.set_path(&self.path);
+        if !self.has_body() && !parameters_str.is_empty() {
+            url.set_query(Some(parameters_str))
+        }
+        url
+    }
+
+    // Same layout as Duo's reference clients (RFC 2822 with a "-0000" UTC offset)
+    fn formatted_date(&self) -> String {
+        self.date.format("%a, %d %b %Y %H:%M:%S -0000").to_string()
+    }
+
+    fn has_body(&self) -> bool {
+        !matches!(self.method, Method::GET | Method::HEAD)
+    }
+
+    fn body(&self, parameters_str: &str) -> Option<String> {
+        if !self.has_body() {
+            return None;
+        }
+
+        match self.body_encoding {
+            BodyEncoding::Form => Some(parameters_str.into()),
+        }
+    }
+
+    fn canonical_string(&self, parameters_str: &str) -> String {
         let domain = self.url.host_str().unwrap().to_string();
 
-        let payload = &[
-            self.date.to_rfc2822(),
+        [
+            self.formatted_date(),
             self.method.to_string().to_uppercase(),
             domain,
             self.path.clone(),
             parameters_str.into(),
         ]
-        .join("\n");
+        .join("\n")
+    }
 
-        let mut signer = Hmac::<Sha1>::new_from_slice(skey.as_bytes())?;
-        signer.update(payload.as_bytes());
+    fn build_signature(&self, skey: &str, parameters_str: &str) -> Result<String, StdError> {
+        let payload = self.canonical_string(parameters_str);
 
-        let signature = hex::encode(signer.finalize().into_bytes());
+        let signature = match self.algorithm {
+            SignatureAlgorithm::HmacSha1 => {
+                let mut signer = Hmac::<Sha1>::new_from_slice(skey.as_bytes())?;
+                signer.update(payload.as_bytes());
+                hex::encode(signer.finalize().into_bytes())
+            }
+            SignatureAlgorithm::HmacSha512 => {
+                let mut signer = Hmac::<Sha512>::new_from_slice(skey.as_bytes())?;
+                signer.update(payload.as_bytes());
+                hex::encode(signer.finalize().into_bytes())
+            }
+        };
 
         Ok(signature)
     }
 }
+
+/// `Authorization` header value of a signed request: `Basic base64(ikey:signature)`
+pub fn authorization_header(ikey: &str, signature: &str) -> String {
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", ikey, signature))
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureDebug {
+    pub canonical_string: String,
+    pub date: String,
+    pub signature: String,
+}
+
+/// Pre-signing view of a request, reproducible and free of secrets, see [`DuoRequest::canonical`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanonicalRequest {
+    pub method: Method,
+    /// Including the query for requests without a body
+    pub url: Url,
+    pub parameters: BTreeMap<String, String>,
+    pub date: String,
+    /// The string the signature is computed over, but for redacted values
+    pub canonical_string: String,
+}
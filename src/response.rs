@@ -1,6 +1,9 @@
+use std::time::Duration;
+
+use reqwest::header::{self, HeaderMap};
 use serde::Deserialize;
 
-use super::errors::Error;
+use super::errors::{DuoErrorCode, Error};
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "stat", rename_all = "SCREAMING_SNAKE_CASE")]
@@ -9,7 +12,7 @@ pub enum DuoResponse<T> {
         response: T,
     },
     Fail {
-        code: u64,
+        code: u32,
         message: String,
         message_detail: Option<String>,
     },
@@ -24,10 +27,53 @@ impl<T> DuoResponse<T> {
                 message,
                 message_detail,
             } => Err(Error::ApiRequestFailed {
-                code,
+                code: DuoErrorCode::from_code(code),
                 message,
                 message_detail,
+                context: None,
             }),
         }
     }
 }
+
+/// Informational `X-Duo-*` and rate limit headers of the most recent API response
+#[derive(Clone, Debug, Default)]
+pub struct ResponseMetadata {
+    pub headers: HeaderMap,
+}
+
+impl ResponseMetadata {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let headers = headers
+            .iter()
+            .filter(|(name, _)| {
+                let name = name.as_str();
+                name.starts_with("x-duo-")
+                    || name.starts_with("x-ratelimit-")
+                    || name.starts_with("ratelimit-")
+                    || name == header::RETRY_AFTER
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        Self { headers }
+    }
+
+    /// Requests left in the current rate limit window, if reported
+    pub fn rate_limit_remaining(&self) -> Option<u64> {
+        self.number("x-ratelimit-remaining")
+            .or_else(|| self.number("ratelimit-remaining"))
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.number(header::RETRY_AFTER.as_str())
+            .map(Duration::from_secs)
+    }
+
+    fn number(&self, name: &str) -> Option<u64> {
+        self.headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+    }
+}
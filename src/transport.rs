@@ -0,0 +1,31 @@
+use std::sync::Mutex;
+
+use reqwest::{Request, Response};
+use tower::{util::BoxCloneService, BoxError, ServiceExt};
+
+use super::errors::Error;
+
+/// Caller-provided transport, shared by all requests of a client
+pub(crate) struct Transport(Mutex<BoxCloneService<Request, Response, BoxError>>);
+
+impl Transport {
+    pub(crate) fn new(service: BoxCloneService<Request, Response, BoxError>) -> Self {
+        Self(Mutex::new(service))
+    }
+
+    pub(crate) async fn call(&self, request: Request) -> Result<Response, Error> {
+        // Each request drives its own clone, like tower's Buffer would
+        let service = self.0.lock().unwrap().clone();
+
+        service
+            .oneshot(request)
+            .await
+            .map_err(|err| match err.downcast::<reqwest::Error>() {
+                Ok(err) => Error::from_send(*err),
+                Err(err) => Error::Transport {
+                    cause: err,
+                    context: None,
+                },
+            })
+    }
+}
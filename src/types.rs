@@ -1,19 +1,25 @@
-use serde::Deserialize;
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_with::NoneAsEmptyString;
 
-use super::request::Parameters;
+use super::{errors::Error, request::Parameters};
 
 structstruck::strike! {
     #[strikethrough[serde_as]]
-    #[strikethrough[derive(Debug, Deserialize)]]
+    #[strikethrough[derive(Clone, Debug, Deserialize)]]
     #[serde(rename_all = "snake_case")]
     #[serde(tag = "result")]
     pub enum PreauthResponse {
         Auth {
             devices: Vec<pub struct Device {
                 pub capabilities: Option<Vec<pub enum DeviceCapability {
-                    #![derive(PartialEq, Eq, PartialOrd, Ord)]
+                    #![derive(Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
                     #![serde(rename_all = "snake_case")]
 
                     Auto,
@@ -21,6 +27,9 @@ structstruck::strike! {
                     Sms,
                     Phone,
                     MobileOtp,
+                    /// Capability without a matching factor in this crate
+                    #[serde(other)]
+                    Unknown,
                 }>>,
                 pub device: String,
                 pub display_name: Option<String>,
@@ -30,20 +39,132 @@ structstruck::strike! {
                 pub number: Option<String>,
                 pub sms_nextcode: Option<String>,
                 pub r#type: pub enum DeviceType {
-                    #![derive(PartialEq, Eq, PartialOrd, Ord)]
+                    #![derive(Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
                     #![serde(rename_all = "snake_case")]
 
                     Phone,
                     Token,
+                    #[serde(other)]
+                    Unknown,
                 },
 
             }>,
+            #[serde(default)]
+            status_msg: String,
+            user_id: Option<String>,
         },
         Enroll {
             enroll_portal_url: String,
+            #[serde(default)]
+            status_msg: String,
+        },
+        Allow {
+            #[serde(default)]
+            status_msg: String,
+            user_id: Option<String>,
+        },
+        Deny {
+            #[serde(default)]
+            status_msg: String,
+            user_id: Option<String>,
         },
-        Allow,
-        Deny,
+    }
+}
+
+impl DeviceCapability {
+    /// Usable factors, strongest first
+    pub const STRENGTH_ORDER: [DeviceCapability; 4] = [
+        DeviceCapability::Push,
+        DeviceCapability::MobileOtp,
+        DeviceCapability::Phone,
+        DeviceCapability::Sms,
+    ];
+
+    /// Short English name for showing the factor to users
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Auto => "Automatic",
+            Self::Push => "Duo Push",
+            Self::Sms => "SMS passcode",
+            Self::Phone => "Phone call",
+            // Authenticated with `factor=passcode`, see `AuthRequestFactor::passcode`
+            Self::MobileOtp => "Duo Mobile passcode",
+            Self::Unknown => "Unknown",
+        }
+    }
+}
+
+impl Device {
+    pub fn supports(&self, capability: DeviceCapability) -> bool {
+        self.capabilities
+            .as_ref()
+            .map(|c| c.contains(&capability))
+            .unwrap_or(false)
+    }
+
+    pub fn strongest_capability(&self) -> Option<DeviceCapability> {
+        self.preferred_capability(&DeviceCapability::STRENGTH_ORDER)
+    }
+
+    pub fn preferred_capability(
+        &self,
+        preference: &[DeviceCapability],
+    ) -> Option<DeviceCapability> {
+        preference.iter().copied().find(|c| self.supports(*c))
+    }
+
+    /// Device and capability ranking first in `preference` across all `devices`
+    pub fn recommend<'a>(
+        devices: &'a [Device],
+        preference: &[DeviceCapability],
+    ) -> Option<(&'a Device, DeviceCapability)> {
+        preference.iter().find_map(|capability| {
+            devices
+                .iter()
+                .find(|d| d.supports(*capability))
+                .map(|d| (d, *capability))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreauthResult {
+    Auth,
+    Enroll,
+    Allow,
+    Deny,
+}
+
+impl PreauthResponse {
+    pub fn result(&self) -> PreauthResult {
+        match self {
+            Self::Auth { .. } => PreauthResult::Auth,
+            Self::Enroll { .. } => PreauthResult::Enroll,
+            Self::Allow { .. } => PreauthResult::Allow,
+            Self::Deny { .. } => PreauthResult::Deny,
+        }
+    }
+
+    /// Human readable (English) message, prefer keying off [`PreauthResponse::result`]
+    pub fn status_msg(&self) -> &str {
+        match self {
+            Self::Auth { status_msg, .. }
+            | Self::Enroll { status_msg, .. }
+            | Self::Allow { status_msg, .. }
+            | Self::Deny { status_msg, .. } => status_msg,
+        }
+    }
+
+    /// Duo's id of the user, when included in the response. It isn't part of Duo's documented
+    /// response and never is for users still to enroll, so be prepared for `None`.
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            Self::Auth { user_id, .. }
+            | Self::Allow { user_id, .. }
+            | Self::Deny { user_id, .. } => user_id.as_deref(),
+            Self::Enroll { .. } => None,
+        }
     }
 }
 
@@ -59,6 +180,7 @@ structstruck::strike! {
             Waiting,
         },
         pub status: pub enum AuthStatus {
+            #![derive(Clone, Copy, PartialEq, Eq)]
             #![serde(rename_all = "snake_case")]
 
             Calling,
@@ -72,12 +194,104 @@ structstruck::strike! {
             Deny,
             LockedOut,
             Sent,
+            #[serde(other)]
+            Unknown,
         },
         pub status_msg: String,
         pub trusted_device_token: Option<String>,
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthDecision {
+    Allow,
+    Deny,
+    /// The user couldn't be reached: the call or push went unanswered (e.g. to voicemail), or
+    /// the push couldn't be delivered. Denied, but not by the user.
+    NoAnswer,
+    /// SMS passcodes were delivered; the user still has to auth with one of them
+    PasscodesSent,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuthOutcome {
+    pub decision: AuthDecision,
+    pub status: AuthStatus,
+    /// Number of `auth_status` requests made
+    pub polls: u32,
+    pub elapsed: Duration,
+    /// Issued on allow when the "remember devices" policy is on, see [`PreauthRequest`]
+    pub trusted_device_token: Option<String>,
+}
+
+/// Status change yielded by `DuoClient::auth_status_timed_stream`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedAuthStatus {
+    pub status: AuthStatus,
+    /// Duration of the `auth_status` request reporting the status. It long-polls until the
+    /// status changes, so this includes waiting for e.g. the user to answer.
+    pub latency: Duration,
+    /// Since the stream was created
+    pub elapsed: Duration,
+}
+
+/// Final outcomes of the client's auth poll loops, see `DuoClient::stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AuthStats {
+    pub allowed: u64,
+    /// Denials other than lockouts and unanswered prompts
+    pub denied: u64,
+    pub locked_out: u64,
+    /// Users that couldn't be reached ([`AuthDecision::NoAnswer`]), and polls giving up at the
+    /// poll timeout
+    pub timed_out: u64,
+    /// Polls failing with any other error
+    pub failed: u64,
+}
+
+/// Result of `DuoClient::reauth_with_token`
+#[derive(Clone, Debug)]
+pub struct ReauthOutcome {
+    pub allowed: bool,
+    /// Decided by preauth without prompting the user, i.e. the token was still accepted
+    pub silent: bool,
+    /// Token to present on the next reauth
+    pub trusted_device_token: Option<String>,
+}
+
+/// Result of `DuoClient::preauth_or_stale`
+#[derive(Clone, Debug)]
+pub struct PreauthOutcome {
+    pub response: PreauthResponse,
+    /// Set when Duo was unavailable and this is a cached response, to its age
+    pub stale_for: Option<Duration>,
+}
+
+impl PreauthOutcome {
+    pub fn is_stale(&self) -> bool {
+        self.stale_for.is_some()
+    }
+}
+
+impl AuthOutcome {
+    pub fn allowed(&self) -> bool {
+        self.decision == AuthDecision::Allow
+    }
+
+    /// Allowed via a bypass code or a bypass policy rather than by the user approving, which
+    /// usually warrants an audit trail. Preauth `allow` results never get this far.
+    pub fn bypassed(&self) -> bool {
+        self.allowed() && self.status == AuthStatus::Bypass
+    }
+}
+
+impl AuthStatus {
+    /// Whether the transaction is still in progress, e.g. while calling or after answering
+    pub fn is_intermediate(&self) -> bool {
+        matches!(self, Self::Calling | Self::Answered | Self::Pushed)
+    }
+}
+
 impl AuthStatusResponse {
     pub fn ready(&self) -> Option<bool> {
         match self.result {
@@ -86,6 +300,19 @@ impl AuthStatusResponse {
             AuthResult::Waiting => None,
         }
     }
+
+    pub fn decision(&self) -> Option<AuthDecision> {
+        match (&self.result, &self.status) {
+            (AuthResult::Allow, _) => Some(AuthDecision::Allow),
+            // Duo reports factor=sms as a deny with "sent" status
+            (AuthResult::Deny, AuthStatus::Sent) => Some(AuthDecision::PasscodesSent),
+            (AuthResult::Deny, AuthStatus::Timeout | AuthStatus::PushFailed) => {
+                Some(AuthDecision::NoAnswer)
+            }
+            (AuthResult::Deny, _) => Some(AuthDecision::Deny),
+            (AuthResult::Waiting, _) => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -102,6 +329,14 @@ impl User {
         };
     }
 
+    /// Same as the request parameter, e.g. `username=alice`
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Self::UserId { id } => format!("user_id={}", id),
+            Self::Username { username } => format!("username={}", username),
+        }
+    }
+
     pub fn user_id<S: Into<String>>(id: S) -> Self {
         Self::UserId { id: id.into() }
     }
@@ -119,6 +354,8 @@ pub struct PreauthRequest {
     pub ipaddr: Option<String>,
     pub hostname: Option<String>,
     pub trusted_device_token: Option<String>,
+    /// Parameters not modelled by this crate, signed along with the rest
+    pub extra_params: Parameters,
 }
 
 impl PreauthRequest {
@@ -128,14 +365,21 @@ impl PreauthRequest {
             ipaddr: None,
             hostname: None,
             trusted_device_token: None,
+            extra_params: Parameters::default(),
         }
     }
 
-    pub(crate) fn apply(self, parameters: &mut Parameters) {
+    pub fn with_param<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.extra_params.set(k, v);
+        self
+    }
+
+    pub(crate) fn apply(self, parameters: &mut Parameters) -> Result<(), Error> {
         self.user.apply(parameters);
         parameters.set_opt("ipaddr", self.ipaddr);
         parameters.set_opt("hostname", self.hostname);
         parameters.set_opt("trusted_device_token", self.trusted_device_token);
+        parameters.merge_extra(self.extra_params)
     }
 }
 
@@ -162,6 +406,8 @@ structstruck::strike! {
         },
         pub ipaddr: Option<String>,
         pub hostname: Option<String>,
+        /// Parameters not modelled by this crate, signed along with the rest
+        pub extra_params: Parameters,
     }
 }
 
@@ -172,14 +418,21 @@ impl AuthRequest {
             factor,
             ipaddr: None,
             hostname: None,
+            extra_params: Parameters::default(),
         }
     }
 
-    pub(crate) fn apply(self, parameters: &mut Parameters) {
+    pub fn with_param<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Self {
+        self.extra_params.set(k, v);
+        self
+    }
+
+    pub(crate) fn apply(self, parameters: &mut Parameters) -> Result<(), Error> {
         self.user.apply(parameters);
-        self.factor.apply(parameters);
+        self.factor.apply(parameters)?;
         parameters.set_opt("ipaddr", self.ipaddr);
         parameters.set_opt("hostname", self.hostname);
+        parameters.merge_extra(self.extra_params)
     }
 }
 
@@ -193,7 +446,44 @@ impl AuthRequestFactor {
         }
     }
 
-    pub(crate) fn apply(self, parameters: &mut Parameters) {
+    pub fn push<S: Into<String>>(device: S) -> Self {
+        Self::Push {
+            device: device.into(),
+            r#type: None,
+            display_username: None,
+            push_info: None,
+        }
+    }
+
+    /// Any passcode: from Duo Mobile (`mobile_otp`), SMS, a hardware token or bypass codes
+    pub fn passcode<S: Into<String>>(passcode: S) -> Self {
+        Self::Passcode {
+            passcode: passcode.into(),
+        }
+    }
+
+    pub fn auto_with_device<S: Into<String>>(device: S) -> Self {
+        Self::Auto {
+            device: Some(device.into()),
+            r#type: None,
+            display_username: None,
+            push_info: None,
+        }
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            Self::Auto { device, .. } => device.as_deref(),
+            Self::Push { device, .. } | Self::Phone { device } | Self::Sms { device } => {
+                Some(device)
+            }
+            Self::Passcode { .. } => None,
+        }
+    }
+
+    /// Fails with [`Error::MissingParameter`] when the factor lacks a value Duo requires, and
+    /// with [`Error::ParameterTooLong`] when a push parameter exceeds its limit
+    pub(crate) fn apply(self, parameters: &mut Parameters) -> Result<(), Error> {
         match self {
             Self::Auto {
                 device,
@@ -203,9 +493,12 @@ impl AuthRequestFactor {
             } => {
                 parameters.set("factor", "auto");
                 parameters.set_opt("device", device);
-                parameters.set_opt("type", r#type);
-                parameters.set_opt("display_username", display_username);
-                parameters.set_opt("push_info", push_info);
+                parameters.set_opt("type", bounded("type", r#type, MAX_PUSH_TYPE)?);
+                parameters.set_opt(
+                    "display_username",
+                    bounded("display_username", display_username, MAX_DISPLAY_USERNAME)?,
+                );
+                parameters.set_opt("push_info", bounded("push_info", push_info, MAX_PUSH_INFO)?);
             }
             Self::Push {
                 device,
@@ -214,33 +507,96 @@ impl AuthRequestFactor {
                 push_info,
             } => {
                 parameters.set("factor", "push");
-                parameters.set("device", device);
-                parameters.set_opt("type", r#type);
-                parameters.set_opt("display_username", display_username);
-                parameters.set_opt("push_info", push_info);
+                parameters.set("device", required("device", device)?);
+                parameters.set_opt("type", bounded("type", r#type, MAX_PUSH_TYPE)?);
+                parameters.set_opt(
+                    "display_username",
+                    bounded("display_username", display_username, MAX_DISPLAY_USERNAME)?,
+                );
+                parameters.set_opt("push_info", bounded("push_info", push_info, MAX_PUSH_INFO)?);
             }
             Self::Passcode { passcode } => {
                 parameters.set("factor", "passcode");
-                parameters.set("passcode", passcode);
+                parameters.set("passcode", required("passcode", passcode)?);
             }
             Self::Phone { device } => {
                 parameters.set("factor", "phone");
-                parameters.set("device", device);
+                parameters.set("device", required("device", device)?);
             }
             Self::Sms { device } => {
                 parameters.set("factor", "sms");
-                parameters.set("device", device);
+                parameters.set("device", required("device", device)?);
             }
-        };
+        }
+
+        Ok(())
+    }
+}
+
+// Duo requires less than 20,000 bytes
+const MAX_PUSH_INFO: usize = 19_999;
+// Duo documents no limit for these, longer values don't fit the Duo Mobile prompt and get cut
+const MAX_PUSH_TYPE: usize = 100;
+const MAX_DISPLAY_USERNAME: usize = 100;
+
+/// Limits are in bytes, a multibyte character counts once per byte
+fn bounded(name: &'static str, value: Option<String>, max: usize) -> Result<Option<String>, Error> {
+    match value {
+        Some(value) if value.len() > max => Err(Error::ParameterTooLong { name, max }),
+        value => Ok(value),
+    }
+}
+
+fn required(name: &'static str, value: String) -> Result<String, Error> {
+    if value.is_empty() {
+        return Err(Error::MissingParameter(name));
+    }
+    Ok(value)
+}
+
+/// Id of an auth transaction, as returned by `/auth/v2/auth`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Txid(String);
+
+impl Txid {
+    /// Wraps a txid obtained elsewhere, e.g. read back from storage
+    pub fn new<S: Into<String>>(txid: S) -> Self {
+        Self(txid.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Txid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Txid> for String {
+    fn from(value: Txid) -> Self {
+        value.0
     }
 }
 
+/// In-flight auth transaction, persistable so another process can resume polling it
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SavedAuth {
+    pub txid: Txid,
+    /// Local time the transaction was created at
+    pub created_at: Timestamp,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct EnrollResponse {
     pub activation_barcode: String,
     pub activation_code: String,
-    pub expiration: u64,
+    pub expiration: Timestamp,
     pub user_id: String,
+    /// As requested, or generated by Duo when enrolling without a username
     pub username: String,
 }
 
@@ -251,3 +607,47 @@ pub enum EnrollStatusResponse {
     Invalid,
     Waiting,
 }
+
+/// Outcome to assume when Duo can't be reached, mirroring the official integrations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailMode {
+    /// Fail open, allow the login
+    Safe,
+    /// Fail closed, deny the login
+    Secure,
+}
+
+/// Unix timestamp (seconds) as reported by Duo
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    pub(crate) fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self(secs)
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.0)
+    }
+
+    pub fn to_datetime(&self) -> Option<DateTime<Utc>> {
+        i64::try_from(self.0)
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(value: Timestamp) -> Self {
+        value.to_system_time()
+    }
+}
@@ -0,0 +1,363 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    builder::Timeouts,
+    errors::Error,
+    request::Parameters,
+    types::{AuthRequest, AuthRequestFactor, AuthStatus, Txid, User},
+    DuoClient,
+};
+use serde_json::json;
+
+fn push(display_username: Option<&str>) -> AuthRequest {
+    let factor = AuthRequestFactor::Push {
+        device: "auto".into(),
+        r#type: None,
+        display_username: display_username.map(String::from),
+        push_info: None,
+    };
+    AuthRequest::new(User::username("user"), factor)
+}
+
+#[tokio::test]
+async fn unset_display_username_is_omitted() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    client.auth(push(None)).await.unwrap();
+    client.auth(push(Some("Share 1"))).await.unwrap();
+
+    let requests = server.requests("/auth/v2/auth");
+    assert_eq!(
+        requests[0].body,
+        "async=1&device=auto&factor=push&username=user"
+    );
+    assert_eq!(
+        requests[1].body,
+        "async=1&device=auto&display_username=Share%201&factor=push&username=user"
+    );
+}
+
+#[tokio::test]
+async fn collected_extra_params_are_sent() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let mut request = push(None);
+    request.extra_params = [("pushinfo_v", "2"), ("channel", "web")]
+        .into_iter()
+        .collect::<Parameters>();
+    // Later values replace earlier ones
+    request
+        .extra_params
+        .extend([("channel".to_string(), "vpn".to_string())]);
+    client.auth(request).await.unwrap();
+
+    assert_eq!(
+        server.requests("/auth/v2/auth")[0].body,
+        "async=1&channel=vpn&device=auto&factor=push&pushinfo_v=2&username=user"
+    );
+}
+
+#[tokio::test]
+async fn reserved_extra_params_are_rejected() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    for name in ["async", "username", "Authorization"] {
+        let err = client
+            .auth(push(None).with_param(name, "x"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::ReservedParameter(n) if n == name),
+            "{name}: {err}"
+        );
+    }
+    assert!(server.requests("/auth/v2/auth").is_empty());
+
+    client
+        .auth(push(None).with_param("txid_tag", "x"))
+        .await
+        .unwrap();
+    assert_eq!(
+        server.requests("/auth/v2/auth")[0].body,
+        "async=1&device=auto&factor=push&txid_tag=x&username=user"
+    );
+}
+
+#[tokio::test]
+async fn auth_timing_out_after_sending_is_uncertain() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" }))
+            .delayed(Duration::from_secs(2)),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_timeouts(Timeouts {
+            auth: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        })
+        .build()
+        .unwrap();
+
+    let err = client.auth(push(None)).await.unwrap_err();
+
+    assert!(
+        matches!(&err, Error::AuthCreationUncertain { .. }),
+        "{err:?}"
+    );
+    assert_eq!(server.requests("/auth/v2/auth").len(), 1);
+}
+
+#[tokio::test]
+async fn push_info_is_limited_in_bytes() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let push = |push_info: String| {
+        let factor = AuthRequestFactor::Push {
+            device: "auto".into(),
+            r#type: None,
+            display_username: None,
+            push_info: Some(push_info),
+        };
+        AuthRequest::new(User::username("user"), factor)
+    };
+
+    // 10,000 two byte characters
+    let err = client.auth(push("ü".repeat(10_000))).await.unwrap_err();
+    assert!(
+        matches!(
+            &err,
+            Error::ParameterTooLong {
+                name: "push_info",
+                max: 19_999
+            }
+        ),
+        "{err}"
+    );
+    assert!(server.requests("/auth/v2/auth").is_empty());
+
+    client.auth(push("a".repeat(19_999))).await.unwrap();
+    assert_eq!(server.requests("/auth/v2/auth").len(), 1);
+}
+
+#[tokio::test]
+async fn push_type_and_display_username_are_limited() {
+    let server = MockDuoServer::start().await;
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let push = |r#type: &str, display_username: &str| {
+        let factor = AuthRequestFactor::Push {
+            device: "auto".into(),
+            r#type: Some(r#type.into()),
+            display_username: Some(display_username.into()),
+            push_info: None,
+        };
+        AuthRequest::new(User::username("user"), factor)
+    };
+
+    // 51 two byte characters
+    let long = "ü".repeat(51);
+    let err = client.auth(push(&long, "Share 1")).await.unwrap_err();
+    assert!(
+        matches!(
+            &err,
+            Error::ParameterTooLong {
+                name: "type",
+                max: 100
+            }
+        ),
+        "{err}"
+    );
+    let err = client.auth(push("Login", &long)).await.unwrap_err();
+    assert!(
+        matches!(
+            &err,
+            Error::ParameterTooLong {
+                name: "display_username",
+                max: 100
+            }
+        ),
+        "{err}"
+    );
+    assert!(server.requests("/auth/v2/auth").is_empty());
+}
+
+#[tokio::test]
+async fn sms_passcodes_are_sent_synchronously() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({
+            "result": "deny",
+            "status": "sent",
+            "status_msg": "New SMS passcodes sent",
+        })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    client
+        .send_sms_passcodes(User::username("user"), "phone1")
+        .await
+        .unwrap();
+
+    let requests = server.requests("/auth/v2/auth");
+    assert_eq!(requests[0].body, "device=phone1&factor=sms&username=user");
+    assert!(server.requests("/auth/v2/auth_status").is_empty());
+}
+
+#[tokio::test]
+async fn unsent_sms_passcodes_report_the_status() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({
+            "result": "deny",
+            "status": "locked_out",
+            "status_msg": "Your account is locked out",
+        })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let result = client
+        .send_sms_passcodes(User::username("user"), "phone1")
+        .await;
+
+    assert!(
+        matches!(
+            &result,
+            Err(Error::PasscodesNotSent {
+                status: AuthStatus::LockedOut,
+                ..
+            })
+        ),
+        "{result:?}"
+    );
+}
+
+#[tokio::test]
+async fn auto_factor_names_the_unknown_device() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::json(
+            400,
+            json!({
+                "stat": "FAIL",
+                "code": 40002,
+                "message": "Invalid request parameters",
+                "message_detail": "device",
+            }),
+        ),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let request = AuthRequest::new(
+        User::username("user"),
+        AuthRequestFactor::auto_with_device("DPFZRS9FB0D46QFTM891"),
+    );
+
+    let err = client.auth(request).await.unwrap_err();
+
+    assert!(
+        matches!(&err, Error::UnknownDevice { device, .. } if device == "DPFZRS9FB0D46QFTM891"),
+        "{err:?}"
+    );
+    assert_eq!(
+        server.requests("/auth/v2/auth")[0].body,
+        "async=1&device=DPFZRS9FB0D46QFTM891&factor=auto&username=user"
+    );
+}
+
+#[tokio::test]
+async fn expired_transactions_are_invalid() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth_status",
+        MockResponse::json(
+            400,
+            json!({
+                "stat": "FAIL",
+                "code": 40002,
+                "message": "Invalid request parameters",
+                "message_detail": "txid",
+            }),
+        ),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.auth_status(&Txid::new("TX1")).await.unwrap_err();
+
+    assert!(
+        matches!(&err, Error::InvalidTransaction { txid, .. } if txid == "TX1"),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn status_of_another_transaction_is_rejected() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth_status",
+        MockResponse::ok(json!({
+            "txid": "TX2",
+            "result": "allow",
+            "status": "allow",
+            "status_msg": "Success. Logging you in...",
+        })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.auth_status(&Txid::new("TX1")).await.unwrap_err();
+
+    assert!(
+        matches!(
+            &err,
+            Error::ResponseMismatch { expected, actual, .. } if expected == "TX1" && actual == "TX2"
+        ),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn raw_auth_status_keeps_unmodelled_fields() {
+    let server = MockDuoServer::start().await;
+    let response = json!({
+        "result": "waiting",
+        "status": "pushed",
+        "status_msg": "Pushed a login request to your phone...",
+        "auth_context": { "factor": "Duo Push" },
+    });
+    server.respond("/auth/v2/auth_status", MockResponse::ok(response.clone()));
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let raw = client.auth_status_raw(&Txid::new("TX1")).await.unwrap();
+
+    assert_eq!(raw, response);
+    assert_eq!(
+        server.requests("/auth/v2/auth_status")[0].query.as_deref(),
+        Some("txid=TX1")
+    );
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use chrono::{TimeZone, Utc};
+use duo_auth::request::{DuoRequest, Parameters};
+use proptest::{collection::btree_map, prelude::*};
+use reqwest::{Method, Url};
+
+// Unicode, reserved characters and empty strings
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(String::new()),
+        "[ -~]{0,16}",
+        "\\PC{0,16}",
+        "[&=+%?#/ ]{1,8}",
+    ]
+}
+
+fn parameters(map: &BTreeMap<String, String>, reversed: bool) -> Parameters {
+    let mut parameters = Parameters::default();
+    let entries: Vec<_> = match reversed {
+        true => map.iter().rev().collect(),
+        false => map.iter().collect(),
+    };
+    for (k, v) in entries {
+        parameters.set(k.clone(), v.clone());
+    }
+    parameters
+}
+
+fn parse(serialized: &str) -> BTreeMap<String, String> {
+    serialized
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap();
+            (
+                urlencoding::decode(k).unwrap().into_owned(),
+                urlencoding::decode(v).unwrap().into_owned(),
+            )
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn canonical_parameters_round_trip(map in btree_map(text(), text(), 0..8)) {
+        let serialized = parameters(&map, false).serialize();
+
+        prop_assert!(serialized
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~%=&".contains(c)));
+        prop_assert_eq!(parse(&serialized), map);
+    }
+
+    #[test]
+    fn canonical_parameters_ignore_insertion_order(map in btree_map(text(), text(), 0..8)) {
+        prop_assert_eq!(
+            parameters(&map, false).serialize(),
+            parameters(&map, true).serialize()
+        );
+    }
+
+    #[test]
+    fn collected_parameters_ignore_insertion_order(map in btree_map(text(), text(), 0..8)) {
+        let reversed: Parameters = map
+            .iter()
+            .rev()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let mut extended = Parameters::default();
+        extended.extend(map.clone());
+
+        prop_assert_eq!(reversed.serialize(), parameters(&map, false).serialize());
+        prop_assert_eq!(extended.serialize(), parameters(&map, false).serialize());
+    }
+
+    #[test]
+    fn canonical_string_is_deterministic(map in btree_map(text(), text(), 0..8)) {
+        let debug = || {
+            DuoRequest::new(
+                Url::parse("https://api-xxxxxxxx.duosecurity.com").unwrap(),
+                Method::POST,
+                "/auth/v2/auth",
+                parameters(&map, false),
+            )
+            .with_date(Utc.with_ymd_and_hms(2012, 8, 21, 17, 29, 18).unwrap())
+            .debug_signature("secret")
+            .unwrap()
+        };
+
+        let (first, second) = (debug(), debug());
+        prop_assert_eq!(&first.canonical_string, &second.canonical_string);
+        prop_assert_eq!(first.signature, second.signature);
+
+        let body = first.canonical_string.rsplit('\n').next().unwrap();
+        prop_assert_eq!(body, parameters(&map, false).serialize());
+    }
+}
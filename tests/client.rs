@@ -0,0 +1,181 @@
+mod common;
+
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{errors::Error, DuoClient};
+use serde_json::json;
+
+#[tokio::test]
+async fn warm_up_pings_within_the_jitter() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/ping",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_warm_up_jitter(Duration::from_millis(200))
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    client.warm_up().await.unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(server.requests("/auth/v2/ping").len(), 1);
+}
+
+fn redirect_checks(server: &MockDuoServer) {
+    server
+        .respond("/auth/v2/check", MockResponse::redirect("/elsewhere"))
+        .respond(
+            "/elsewhere",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+}
+
+#[tokio::test]
+async fn redirects_are_not_followed() {
+    let server = MockDuoServer::start().await;
+    redirect_checks(&server);
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.check().await.unwrap_err();
+
+    assert!(
+        matches!(&err, Error::UnexpectedRedirect { location: Some(l), .. } if l == "/elsewhere"),
+        "{err:?}"
+    );
+    assert!(server.requests("/elsewhere").is_empty());
+}
+
+#[tokio::test]
+async fn custom_clients_follow_redirects_unless_told_not_to() {
+    let server = MockDuoServer::start().await;
+    redirect_checks(&server);
+
+    let following =
+        DuoClient::new_with_client(reqwest::Client::new(), server.url(), IKEY, SKEY).unwrap();
+    following.check().await.unwrap();
+    assert_eq!(server.requests("/elsewhere").len(), 1);
+
+    let custom = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let client = DuoClient::new_with_client(custom, server.url(), IKEY, SKEY).unwrap();
+    let err = client.check().await.unwrap_err();
+    assert!(matches!(&err, Error::UnexpectedRedirect { .. }), "{err:?}");
+    assert_eq!(server.requests("/elsewhere").len(), 1);
+}
+
+#[tokio::test]
+async fn api_version_prefixes_signed_paths() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v1/check",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+    let signed = Arc::new(Mutex::new(Vec::new()));
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_api_version("v1")
+        .on_canonical_request({
+            let signed = Arc::clone(&signed);
+            move |request| {
+                signed
+                    .lock()
+                    .unwrap()
+                    .push(request.canonical_string.clone())
+            }
+        })
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+
+    assert_eq!(server.requests("/auth/v1/check").len(), 1);
+    assert!(server.requests("/auth/v2/check").is_empty());
+    let signed = signed.lock().unwrap();
+    assert!(signed[0].contains("\n/auth/v1/check\n"), "{signed:?}");
+}
+
+#[tokio::test]
+async fn connections_are_made_from_the_local_address() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+    // Any address of 127.0.0.0/8 routes to the loopback interface
+    let local = IpAddr::from([127, 0, 0, 2]);
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_local_address(local)
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+
+    assert_eq!(server.requests("/auth/v2/check")[0].peer.ip(), local);
+}
+
+#[tokio::test]
+async fn connect_timeout_leaves_slow_answers_alone() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::ok(json!({ "time": 1357020061 })).delayed(Duration::from_millis(500)),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_connect_timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_warm_up_leaves_the_client_usable() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/ping",
+            MockResponse::fail(503, 50301, "Service unavailable"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.warm_up().await.unwrap_err();
+    assert!(err.is_unavailable(), "{err:?}");
+
+    client.check().await.unwrap();
+}
+
+#[tokio::test]
+async fn duo_headers_of_the_last_response_are_kept() {
+    let server = MockDuoServer::start().await;
+    let mut response = MockResponse::fail(429, 42901, "Too many requests");
+    response.headers = vec![
+        ("X-Duo-Request-Id", "abc123".into()),
+        ("X-RateLimit-Remaining", "0".into()),
+        ("Retry-After", "3".into()),
+        ("Server", "mock".into()),
+    ];
+    server.respond("/auth/v2/check", response);
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    assert!(client.last_response_metadata().is_none());
+
+    client.check().await.unwrap_err();
+
+    let metadata = client.last_response_metadata().unwrap();
+    assert_eq!(metadata.headers["x-duo-request-id"], "abc123");
+    assert_eq!(metadata.rate_limit_remaining(), Some(0));
+    assert_eq!(metadata.retry_after(), Some(Duration::from_secs(3)));
+    assert!(!metadata.headers.contains_key("server"));
+}
@@ -0,0 +1,249 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+pub const IKEY: &str = "DIWJ8X6AEYOR5OMC6TQ1";
+pub const SKEY: &str = "Zh5eGmUq9zpfQnyUIu5OL9iWoMMv5ZNmk3zLJ4Ep";
+
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    /// Address the request came from
+    pub peer: SocketAddr,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+    /// Time to wait before answering, e.g. like a long-polling `/auth_status`
+    pub delay: Option<Duration>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl MockResponse {
+    pub fn ok(response: serde_json::Value) -> Self {
+        Self::json(
+            200,
+            serde_json::json!({ "stat": "OK", "response": response }),
+        )
+    }
+
+    pub fn fail(status: u16, code: u64, message: &str) -> Self {
+        Self::json(
+            status,
+            serde_json::json!({ "stat": "FAIL", "code": code, "message": message }),
+        )
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+            delay: None,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        let mut response = Self::json(302, serde_json::json!({}));
+        response.headers.push(("Location", location.to_string()));
+        response
+    }
+
+    pub fn delayed(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // The last queued response for a path keeps being served
+    responses: HashMap<String, VecDeque<MockResponse>>,
+    // Same, for requests whose query or body contains the needle; these are served first
+    matching: Vec<(String, String, VecDeque<MockResponse>)>,
+    requests: Vec<RecordedRequest>,
+}
+
+fn next(queue: &mut VecDeque<MockResponse>) -> Option<MockResponse> {
+    match queue.len() {
+        0 | 1 => queue.front().cloned(),
+        _ => queue.pop_front(),
+    }
+}
+
+/// Plain HTTP stand-in for the Duo API, serving queued responses per path
+pub struct MockDuoServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockDuoServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                tokio::spawn(handle(stream, peer, Arc::clone(&server_state)));
+            }
+        });
+
+        Self { addr, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn respond(&self, path: &str, response: MockResponse) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .entry(path.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Like `respond`, only for requests to `path` whose query or body contains `needle`, e.g.
+    /// `txid=...` to tell concurrent transactions apart
+    pub fn respond_matching(&self, path: &str, needle: &str, response: MockResponse) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .matching
+            .iter_mut()
+            .find(|(p, n, _)| p == path && n == needle)
+        {
+            Some((_, _, queue)) => queue.push_back(response),
+            None => state.matching.push((
+                path.to_string(),
+                needle.to_string(),
+                VecDeque::from([response]),
+            )),
+        }
+        self
+    }
+
+    pub fn requests(&self, path: &str) -> Vec<RecordedRequest> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|r| r.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+async fn handle(mut stream: TcpStream, peer: SocketAddr, state: Arc<Mutex<State>>) {
+    let mut buf = Vec::new();
+    let header_end = loop {
+        let mut chunk = [0u8; 4096];
+        let n = match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default().to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|l| l.split_once(": "))
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.to_string()))
+        .collect();
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < header_end + content_length {
+        let mut chunk = [0u8; 4096];
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let body = String::from_utf8_lossy(&buf[header_end..header_end + content_length]).to_string();
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target, None),
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        let matched = state
+            .matching
+            .iter_mut()
+            .find(|(p, needle, _)| {
+                *p == path
+                    && (query
+                        .as_deref()
+                        .unwrap_or_default()
+                        .contains(needle.as_str())
+                        || body.contains(needle.as_str()))
+            })
+            .and_then(|(_, _, queue)| next(queue));
+        state.requests.push(RecordedRequest {
+            peer,
+            method,
+            path: path.clone(),
+            query,
+            headers,
+            body,
+        });
+
+        matched
+            .or_else(|| state.responses.get_mut(&path).and_then(next))
+            .unwrap_or_else(|| MockResponse::fail(404, 40401, "Resource not found"))
+    };
+
+    if let Some(delay) = response.delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    let headers: String = response
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect();
+    let raw = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        headers,
+        response.body
+    );
+    let _ = stream.write_all(raw.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::Error,
+    types::{EnrollResponse, PreauthRequest, Timestamp, User},
+    DuoClient,
+};
+use serde_json::json;
+
+#[tokio::test]
+async fn enroll_without_username_returns_generated_one() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/enroll",
+        MockResponse::ok(json!({
+            "activation_barcode": "https://api-abcdef.duosecurity.com/frame/qr?value=8LIRa5danrICkhHtkLxi-cKLu2DWzDYCmBwBHY2YzW5ZYnYaRxA",
+            "activation_code": "duo://8LIRa5danrICkhHtkLxi-cKLu2DWzDYCmBwBHY2YzW5ZYnYaRxA",
+            "expiration": 1357020061,
+            "user_id": "DU94SWSN4ADHHJHF2HXT",
+            "username": "49c6c3097adb386048c84354d82e5d5d"
+        })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let enrollment = client.enroll(None::<String>, None).await.unwrap();
+
+    assert_eq!(enrollment.username, "49c6c3097adb386048c84354d82e5d5d");
+    assert!(!server.requests("/auth/v2/enroll")[0]
+        .body
+        .contains("username="));
+}
+
+#[tokio::test]
+async fn enroll_existing_username_fails() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/enroll",
+        MockResponse::json(
+            400,
+            json!({
+                "stat": "FAIL",
+                "code": 40002,
+                "message": "Invalid request parameters",
+                "message_detail": "username"
+            }),
+        ),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.enroll(Some("alice"), None).await.unwrap_err();
+
+    assert!(matches!(
+        &err,
+        Error::UsernameTaken { username, .. } if username == "alice"
+    ));
+}
+
+#[tokio::test]
+async fn cached_user_id_is_used_until_rejected() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/enroll",
+        MockResponse::ok(json!({
+            "activation_barcode": "https://api-abcdef.duosecurity.com/frame/qr?value=8LIRa5danrICkhHtkLxi",
+            "activation_code": "duo://8LIRa5danrICkhHtkLxi",
+            "expiration": 1357020061,
+            "user_id": "DU94SWSN4ADHHJHF2HXT",
+            "username": "alice"
+        })),
+    );
+    server
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({ "result": "allow", "status_msg": "" })),
+        )
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::json(
+                400,
+                json!({
+                    "stat": "FAIL",
+                    "code": 40002,
+                    "message": "Invalid request parameters",
+                    "message_detail": "user_id"
+                }),
+            ),
+        )
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({ "result": "allow", "status_msg": "" })),
+        );
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_user_id_cache(Duration::from_secs(60), 16)
+        .build()
+        .unwrap();
+
+    client.enroll(Some("alice"), None).await.unwrap();
+    let preauth = || PreauthRequest::new(User::username("alice"));
+    client.preauth(preauth()).await.unwrap();
+    client.preauth(preauth()).await.unwrap();
+
+    let bodies: Vec<_> = server
+        .requests("/auth/v2/preauth")
+        .into_iter()
+        .map(|r| r.body)
+        .collect();
+    assert_eq!(
+        bodies,
+        [
+            "user_id=DU94SWSN4ADHHJHF2HXT",
+            "user_id=DU94SWSN4ADHHJHF2HXT",
+            "username=alice",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn enrollment_qr_is_only_fetched_from_the_api_origin() {
+    let server = MockDuoServer::start().await;
+    server.respond("/frame/qr", MockResponse::json(200, json!("png")));
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let addr = server.url().strip_prefix("http://").unwrap().to_string();
+    let (host, port) = addr.split_once(':').unwrap();
+    let other_port = port.parse::<u16>().unwrap().wrapping_add(1);
+    let enrollment = |origin: &str| EnrollResponse {
+        activation_barcode: format!("{origin}/frame/qr?value=8LIRa5danrICkhHtkLxi"),
+        activation_code: "duo://8LIRa5danrICkhHtkLxi".into(),
+        expiration: Timestamp(1357020061),
+        user_id: "DU94SWSN4ADHHJHF2HXT".into(),
+        username: "alice".into(),
+    };
+
+    for origin in [
+        format!("https://{addr}"),
+        format!("http://{host}:{other_port}"),
+        "http://api-abcdef.duosecurity.com".to_string(),
+    ] {
+        let err = client
+            .fetch_enrollment_qr(&enrollment(&origin))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::UntrustedBarcodeUrl { origin: o } if *o == origin),
+            "{err:?}"
+        );
+    }
+    assert!(server.requests("/frame/qr").is_empty());
+
+    let qr = client
+        .fetch_enrollment_qr(&enrollment(&server.url()))
+        .await
+        .unwrap();
+    assert_eq!(qr, b"\"png\"");
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "poll-loop")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::Error,
+    types::{AuthRequest, AuthRequestFactor, FailMode, User},
+    DuoClient,
+};
+use serde_json::json;
+
+fn request() -> AuthRequest {
+    AuthRequest::new(User::username("user"), AuthRequestFactor::auto())
+}
+
+fn client(url: String) -> DuoClient {
+    DuoClient::builder(url, IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(50))
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unreachable_duo_resolves_per_fail_mode() {
+    let client = client("http://127.0.0.1:1".into());
+
+    assert!(client
+        .authenticate_with_failmode(request(), FailMode::Safe)
+        .await
+        .unwrap());
+    assert!(!client
+        .authenticate_with_failmode(request(), FailMode::Secure)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn server_error_creating_the_auth_never_fails_open() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::fail(503, 50301, "Service unavailable"),
+    );
+
+    let result = client(server.url())
+        .authenticate_with_failmode(request(), FailMode::Safe)
+        .await;
+
+    assert!(
+        matches!(result, Err(Error::AuthCreationUncertain { .. })),
+        "{result:?}"
+    );
+}
+
+#[tokio::test]
+async fn server_error_while_polling_never_fails_open() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond(
+            "/auth/v2/auth_status",
+            MockResponse::ok(json!({ "result": "waiting", "status": "pushed", "status_msg": "" })),
+        )
+        .respond(
+            "/auth/v2/auth_status",
+            MockResponse::fail(502, 50201, "Bad gateway"),
+        );
+
+    let result = client(server.url())
+        .authenticate_with_failmode(request(), FailMode::Safe)
+        .await;
+
+    assert!(
+        matches!(result, Err(Error::ServiceUnavailable { status: 502, .. })),
+        "{result:?}"
+    );
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::DuoClient;
+use serde_json::json;
+
+#[tokio::test]
+async fn connect_failure_fails_over_to_next_host() {
+    // Nothing listens on a port just released
+    let unreachable = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+
+    let client = DuoClient::builder(unreachable, IKEY, SKEY)
+        .with_failover_domain(server.url())
+        .build()
+        .unwrap();
+
+    let time = client.check().await.unwrap();
+
+    assert_eq!(time.as_secs(), 1357020061);
+    let requests = server.requests("/auth/v2/check");
+    assert_eq!(requests.len(), 1);
+    // Signed for the host it was sent to
+    assert_eq!(
+        requests[0].headers.get("host").map(String::as_str),
+        Some(server.url().trim_start_matches("http://"))
+    );
+}
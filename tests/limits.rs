@@ -0,0 +1,90 @@
+mod common;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::Error,
+    types::{EnrollResponse, PreauthRequest, Timestamp, User},
+    DuoClient,
+};
+use serde_json::json;
+
+const LIMIT: usize = 256;
+
+fn client(server: &MockDuoServer, hook: bool) -> DuoClient {
+    let builder = DuoClient::builder(server.url(), IKEY, SKEY).with_max_response_size(LIMIT);
+    match hook {
+        true => builder.on_exchange(|_| {}),
+        false => builder,
+    }
+    .build()
+    .unwrap()
+}
+
+#[tokio::test]
+async fn oversized_responses_fail_with_and_without_exchange_hook() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({
+            "result": "allow",
+            "status_msg": "x".repeat(LIMIT),
+        })),
+    );
+
+    for hook in [false, true] {
+        let err = client(&server, hook)
+            .preauth(PreauthRequest::new(User::username("user")))
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&err, Error::ResponseTooLarge { limit: LIMIT, .. }),
+            "hook {hook}: {err:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn responses_within_the_limit_are_read() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "" })),
+    );
+
+    for hook in [false, true] {
+        client(&server, hook)
+            .preauth(PreauthRequest::new(User::username("user")))
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn oversized_enrollment_qr_fails() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/frame/qr",
+        MockResponse::json(200, json!("x".repeat(LIMIT))),
+    );
+
+    let enrollment = EnrollResponse {
+        activation_barcode: format!("{}/frame/qr?value=8LIRa5danrICkhHtkLxi", server.url()),
+        activation_code: "duo://8LIRa5danrICkhHtkLxi".into(),
+        expiration: Timestamp(1357020061),
+        user_id: "DU94SWSN4ADHHJHF2HXT".into(),
+        username: "alice".into(),
+    };
+
+    for hook in [false, true] {
+        let err = client(&server, hook)
+            .fetch_enrollment_qr(&enrollment)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&err, Error::ResponseTooLarge { limit: LIMIT, .. }),
+            "hook {hook}: {err:?}"
+        );
+    }
+}
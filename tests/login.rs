@@ -0,0 +1,55 @@
+#![cfg(feature = "poll-loop")]
+
+mod common;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::Error,
+    types::{AuthRequestFactor, User},
+    DuoClient,
+};
+use serde_json::json;
+
+#[tokio::test]
+async fn preauth_allow_skips_auth() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "Allowing unknown user" })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    assert!(client.login("user").await.unwrap());
+    assert!(client
+        .interactive_auth(User::username("user"), |_| AuthRequestFactor::auto())
+        .await
+        .unwrap());
+
+    assert_eq!(server.requests("/auth/v2/preauth").len(), 2);
+    assert!(server.requests("/auth/v2/auth").is_empty());
+    assert!(server.requests("/auth/v2/auth_status").is_empty());
+}
+
+#[tokio::test]
+async fn preauth_auth_without_devices_fails() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(
+            json!({ "result": "auth", "status_msg": "Account is active", "devices": [] }),
+        ),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.login("user").await.unwrap_err();
+    assert!(matches!(&err, Error::NoDevices), "{err}");
+    let err = client
+        .interactive_auth(User::username("user"), |_| unreachable!())
+        .await
+        .unwrap_err();
+    assert!(matches!(&err, Error::NoDevices), "{err}");
+
+    assert!(server.requests("/auth/v2/auth").is_empty());
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "otel")]
+
+mod common;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    types::{PreauthRequest, User},
+    DuoClient,
+};
+use opentelemetry::{global, trace::SpanKind, Key, Value};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, testing::trace::InMemorySpanExporter,
+    trace::TracerProvider,
+};
+use serde_json::json;
+
+#[tokio::test]
+async fn requests_are_traced_without_user_or_secrets() {
+    let exporter = InMemorySpanExporter::default();
+    global::set_tracer_provider(
+        TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build(),
+    );
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "Remembered device" })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let mut preauth = PreauthRequest::new(User::username("alice"));
+    preauth.trusted_device_token = Some("TRUSTEDTOKEN".into());
+    client.preauth(preauth).await.unwrap();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    assert_eq!(spans.len(), 1);
+    let span = &spans[0];
+    assert_eq!(span.name, "POST /auth/v2/preauth");
+    assert_eq!(span.span_kind, SpanKind::Client);
+
+    let attribute = |key: &'static str| {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key == Key::from_static_str(key))
+            .map(|kv| kv.value.clone())
+    };
+    assert_eq!(attribute("http.request.method"), Some("POST".into()));
+    assert_eq!(attribute("url.path"), Some("/auth/v2/preauth".into()));
+    assert_eq!(attribute("server.address"), Some("127.0.0.1".into()));
+    assert_eq!(
+        attribute("http.response.status_code"),
+        Some(Value::I64(200))
+    );
+    for kv in &span.attributes {
+        let value = kv.value.as_str();
+        assert!(
+            !value.contains("alice") && !value.contains("TRUSTEDTOKEN"),
+            "{kv:?}"
+        );
+    }
+
+    // Duo's leg is parented to the span
+    let traceparent = &server.requests("/auth/v2/preauth")[0].headers["traceparent"];
+    let trace_id = span.span_context.trace_id().to_string();
+    let span_id = span.span_context.span_id().to_string();
+    assert!(
+        traceparent.contains(&format!("{trace_id}-{span_id}")),
+        "{traceparent}"
+    );
+}
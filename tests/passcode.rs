@@ -0,0 +1,129 @@
+#![cfg(feature = "poll-loop")]
+
+mod common;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::Error,
+    types::{DeviceCapability, User},
+    DuoClient,
+};
+use serde_json::json;
+
+#[tokio::test]
+async fn detects_mobile_otp_capability() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({
+            "result": "auth",
+            "status_msg": "Account is active",
+            "devices": [
+                {
+                    "device": "DPFZRS9FB0D46QFTM891",
+                    "type": "phone",
+                    "number": "XXX-XXX-0100",
+                    "name": "",
+                    "capabilities": ["mobile_otp", "sms"]
+                },
+                {
+                    "device": "DHEKH0JJIYC1LX3AZWO4",
+                    "type": "token",
+                    "number": "",
+                    "name": ""
+                }
+            ]
+        })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let devices = client.list_devices(User::username("user")).await.unwrap();
+    assert!(devices[0].supports(DeviceCapability::MobileOtp));
+    assert!(!devices[0].supports(DeviceCapability::Push));
+    assert!(!devices[1].supports(DeviceCapability::MobileOtp));
+    assert_eq!(
+        devices[0].strongest_capability(),
+        Some(DeviceCapability::MobileOtp)
+    );
+    assert_eq!(DeviceCapability::MobileOtp.label(), "Duo Mobile passcode");
+
+    let factors = client
+        .available_factors(User::username("user"))
+        .await
+        .unwrap();
+    assert_eq!(
+        factors,
+        vec![DeviceCapability::MobileOtp, DeviceCapability::Sms]
+    );
+}
+
+#[tokio::test]
+async fn auth_passcode_sends_passcode_factor() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" })),
+    );
+    server.respond(
+        "/auth/v2/auth_status",
+        MockResponse::ok(json!({
+            "result": "allow",
+            "status": "allow",
+            "status_msg": "Success. Logging you in..."
+        })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    assert!(client
+        .auth_passcode(User::username("user"), "123456")
+        .await
+        .unwrap());
+
+    let requests = server.requests("/auth/v2/auth");
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].body.contains("factor=passcode"));
+    assert!(requests[0].body.contains("passcode=123456"));
+}
+
+#[tokio::test]
+async fn empty_passcode_fails_before_sending() {
+    let server = MockDuoServer::start().await;
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client
+        .auth_passcode(User::username("user"), "")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, Error::MissingParameter("passcode")));
+    assert!(server.requests("/auth/v2/auth").is_empty());
+}
+
+#[tokio::test]
+async fn auth_passcode_or_push_picks_factor() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::ok(json!({ "txid": "45f7c92b-f45f-4862-8545-e0f58e78075a" })),
+    );
+    server.respond(
+        "/auth/v2/auth_status",
+        MockResponse::ok(json!({ "result": "allow", "status": "allow", "status_msg": "" })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let user = User::username("user");
+    assert!(client
+        .auth_passcode_or_push(user.clone(), Some("123456".into()))
+        .await
+        .unwrap());
+    assert!(client.auth_passcode_or_push(user, None).await.unwrap());
+
+    let requests = server.requests("/auth/v2/auth");
+    assert!(requests[0].body.contains("factor=passcode"));
+    assert!(requests[1].body.contains("factor=push"));
+    assert!(requests[1].body.contains("device=auto"));
+}
@@ -0,0 +1,961 @@
+#![cfg(feature = "poll-loop")]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    builder::Timeouts,
+    errors::Error,
+    types::{
+        AuthDecision, AuthRequest, AuthRequestFactor, AuthStatus, FailMode, PreauthRequest,
+        SavedAuth, Txid, User,
+    },
+    DuoClient, PollConfig,
+};
+use futures_util::StreamExt;
+use serde_json::json;
+
+fn auth_status(result: &str, status: &str) -> MockResponse {
+    MockResponse::ok(json!({ "result": result, "status": status, "status_msg": "" }))
+}
+
+#[tokio::test]
+async fn poll_returns_promptly_after_waiting() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond("/auth/v2/auth_status", auth_status("waiting", "pushed"))
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let started = Instant::now();
+    let outcome = client.auth_outcome(request, |_| {}).await.unwrap();
+    let elapsed = started.elapsed();
+
+    assert!(outcome.allowed());
+    assert_eq!(outcome.polls, 2);
+    // A single poll interval (2s), not a multiple of it
+    assert!(elapsed >= Duration::from_millis(1900), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(3000), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn poll_timeout_falling_mid_sleep_is_not_overshot() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("waiting", "pushed"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(500))
+        .with_poll_timeout(Duration::from_millis(700))
+        .build()
+        .unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let started = Instant::now();
+    let result = client.auth_outcome(request, |_| {}).await;
+    let elapsed = started.elapsed();
+
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), 2);
+    assert!(elapsed >= Duration::from_millis(650), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+}
+
+#[test]
+fn poll_interval_must_be_shorter_than_timeout() {
+    let result = DuoClient::builder("https://api-example.duosecurity.com", IKEY, SKEY)
+        .with_poll_interval(Duration::from_secs(10))
+        .with_poll_timeout(Duration::from_secs(5))
+        .build();
+
+    assert!(matches!(result, Err(Error::InvalidConfig { .. })));
+}
+
+#[tokio::test]
+async fn poll_timeout_is_shared_across_devices() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("waiting", "pushed"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(300))
+        .with_poll_timeout(Duration::from_millis(700))
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    let result = client
+        .auth_any_device(
+            User::username("user"),
+            vec!["PHONE1".into(), "PHONE2".into()],
+        )
+        .await;
+    let elapsed = started.elapsed();
+
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+    assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    assert_eq!(server.requests("/auth/v2/auth").len(), 2);
+
+    // Neither device keeps polling past the deadline
+    let polls = server.requests("/auth/v2/auth_status").len();
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), polls);
+}
+
+#[tokio::test]
+async fn stats_count_outcomes() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"))
+        .respond("/auth/v2/auth_status", auth_status("deny", "locked_out"))
+        .respond("/auth/v2/auth_status", auth_status("deny", "deny"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .count_outcomes(true)
+        .build()
+        .unwrap();
+    for _ in 0..4 {
+        let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+        client.auth_outcome(request, |_| {}).await.unwrap();
+    }
+
+    let stats = client.stats();
+    assert_eq!(stats.allowed, 1);
+    assert_eq!(stats.locked_out, 1);
+    assert_eq!(stats.denied, 2);
+    assert_eq!(stats.timed_out, 0);
+    assert_eq!(stats.failed, 0);
+}
+
+#[tokio::test]
+async fn sent_sms_passcodes_are_not_a_decision() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("deny", "sent"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .count_outcomes(true)
+        .build()
+        .unwrap();
+    let request = || AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let outcome = client.auth_outcome(request(), |_| {}).await.unwrap();
+    assert_eq!(outcome.decision, AuthDecision::PasscodesSent);
+    assert!(!outcome.allowed());
+
+    let result = client.auth_with_progress(request(), |_| {}).await;
+    assert!(matches!(result, Err(Error::PasscodesSent)), "{result:?}");
+    // Neither allowed nor denied
+    assert_eq!(client.stats(), Default::default());
+}
+
+#[tokio::test]
+async fn progress_is_reported_on_status_changes_only() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond("/auth/v2/auth_status", auth_status("waiting", "pushed"))
+        .respond("/auth/v2/auth_status", auth_status("waiting", "pushed"))
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let mut statuses = Vec::new();
+    let allowed = client
+        .auth_with_progress(request, |status| statuses.push(*status))
+        .await
+        .unwrap();
+
+    assert!(allowed);
+    assert_eq!(statuses, [AuthStatus::Pushed, AuthStatus::Allow]);
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), 3);
+}
+
+#[tokio::test]
+async fn bypass_allows_and_is_flagged() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond("/auth/v2/auth_status", auth_status("allow", "bypass"))
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let request = || AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let bypassed = client.auth_outcome(request(), |_| {}).await.unwrap();
+    assert!(bypassed.allowed() && bypassed.bypassed());
+
+    let approved = client.auth_outcome(request(), |_| {}).await.unwrap();
+    assert!(approved.allowed() && !approved.bypassed());
+}
+
+#[tokio::test]
+async fn outcomes_are_only_counted_when_enabled() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    client.auth_outcome(request, |_| {}).await.unwrap();
+
+    assert_eq!(client.stats(), Default::default());
+}
+
+#[tokio::test]
+async fn unanswered_call_is_no_answer() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("deny", "timeout"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .count_outcomes(true)
+        .build()
+        .unwrap();
+    let request = AuthRequest::new(
+        User::username("user"),
+        AuthRequestFactor::Phone {
+            device: "auto".into(),
+        },
+    );
+
+    let outcome = client.auth_outcome(request, |_| {}).await.unwrap();
+
+    assert_eq!(outcome.decision, AuthDecision::NoAnswer);
+    assert!(!outcome.allowed());
+    assert_eq!(client.stats().timed_out, 1);
+}
+
+#[tokio::test]
+async fn idle_timeout_resets_on_progress() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    for status in [
+        "calling", "calling", "calling", "answered", "answered", "answered",
+    ] {
+        server.respond("/auth/v2/auth_status", auth_status("waiting", status));
+    }
+    server.respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let idle = Duration::from_millis(450);
+    let max = Duration::from_secs(5);
+
+    // Each status lasts ~300ms, past the idle timeout only when counted from the start
+    let outcome = client
+        .auth_with_idle_timeout(request, idle, max, |_| {})
+        .await
+        .unwrap();
+    assert!(outcome.allowed());
+}
+
+#[tokio::test]
+async fn idle_timeout_gives_up_without_progress() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("waiting", "pushed"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let started = Instant::now();
+    let result = client
+        .auth_with_idle_timeout(
+            request,
+            Duration::from_millis(450),
+            Duration::from_secs(5),
+            |_| {},
+        )
+        .await;
+
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+fn enrollment() -> MockResponse {
+    MockResponse::ok(json!({
+        "activation_barcode": "https://api-abcdef.duosecurity.com/frame/qr?value=8LIRa5danrICkhHtkLxi",
+        "activation_code": "duo://8LIRa5danrICkhHtkLxi",
+        "expiration": 1357020061,
+        "user_id": "DU94SWSN4ADHHJHF2HXT",
+        "username": "alice"
+    }))
+}
+
+#[tokio::test]
+async fn enroll_and_wait_polls_until_activated() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/enroll", enrollment());
+    server
+        .respond("/auth/v2/enroll_status", MockResponse::ok(json!("waiting")))
+        .respond("/auth/v2/enroll_status", MockResponse::ok(json!("success")));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let mut shown = None;
+    let activated = client
+        .enroll_and_wait(Some("alice"), Duration::from_secs(5), |enrollment| {
+            shown = Some(enrollment.activation_code.clone())
+        })
+        .await
+        .unwrap();
+
+    assert!(activated);
+    assert_eq!(shown.as_deref(), Some("duo://8LIRa5danrICkhHtkLxi"));
+    assert_eq!(
+        server.requests("/auth/v2/enroll")[0].body,
+        "username=alice&valid_secs=5"
+    );
+    let polls = server.requests("/auth/v2/enroll_status");
+    assert_eq!(polls.len(), 2);
+    assert_eq!(
+        polls[0].body,
+        "activation_code=duo%3A%2F%2F8LIRa5danrICkhHtkLxi&user_id=DU94SWSN4ADHHJHF2HXT"
+    );
+}
+
+#[tokio::test]
+async fn enroll_and_wait_gives_up_at_timeout() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/enroll", enrollment());
+    server.respond("/auth/v2/enroll_status", MockResponse::ok(json!("waiting")));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    let err = client
+        .enroll_and_wait(None::<String>, Duration::from_millis(350), |_| {})
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, Error::Timeout), "{err}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(server.requests("/auth/v2/enroll_status").len() >= 3);
+}
+
+#[tokio::test]
+async fn stream_yields_each_phone_call_state_once() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond("/auth/v2/auth_status", auth_status("waiting", "calling"))
+        .respond("/auth/v2/auth_status", auth_status("waiting", "calling"))
+        .respond("/auth/v2/auth_status", auth_status("waiting", "answered"))
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+    let statuses: Vec<_> = client
+        .auth_status_stream(Txid::new("txid"))
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    assert_eq!(
+        statuses,
+        [AuthStatus::Calling, AuthStatus::Answered, AuthStatus::Allow]
+    );
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), 4);
+}
+
+#[tokio::test]
+async fn saved_auth_is_resumed_by_another_client() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "TX1" })));
+    server.respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let starting = DuoClient::builder(server.url(), IKEY, SKEY)
+        .count_outcomes(true)
+        .build()
+        .unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let saved = starting.start_auth(request).await.unwrap();
+    let persisted = serde_json::to_string(&saved).unwrap();
+
+    let resuming = DuoClient::builder(server.url(), IKEY, SKEY)
+        .count_outcomes(true)
+        .build()
+        .unwrap();
+    let restored: SavedAuth = serde_json::from_str(&persisted).unwrap();
+    assert_eq!(restored, saved);
+    let outcome = resuming.resume_poll(restored, |_| {}).await.unwrap();
+
+    assert!(outcome.allowed());
+    assert_eq!(
+        server.requests("/auth/v2/auth_status")[0].query.as_deref(),
+        Some("txid=TX1")
+    );
+    assert_eq!(resuming.stats().allowed, 1);
+    assert_eq!(starting.stats(), Default::default());
+}
+
+#[tokio::test]
+async fn timed_stream_reports_latency_and_elapsed() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond("/auth/v2/auth_status", auth_status("waiting", "pushed"))
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let statuses: Vec<_> = client
+        .auth_status_timed_stream(Txid::new("txid"))
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses[0].status, AuthStatus::Pushed);
+    assert_eq!(statuses[1].status, AuthStatus::Allow);
+    for status in &statuses {
+        assert!(status.latency <= status.elapsed);
+    }
+    assert!(statuses[1].elapsed >= Duration::from_millis(100));
+    assert!(statuses[1].elapsed - statuses[1].latency >= statuses[0].elapsed);
+}
+
+#[tokio::test]
+async fn poll_config_drives_auth_and_enroll_loops() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("waiting", "pushed"));
+    server.respond("/auth/v2/enroll", enrollment());
+    server.respond("/auth/v2/enroll_status", MockResponse::ok(json!("waiting")));
+
+    let config = PollConfig {
+        interval: Duration::from_millis(100),
+        timeout: Some(Duration::from_millis(350)),
+        idle_timeout: None,
+    };
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_config(config)
+        .build()
+        .unwrap();
+    let request = || AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    for enroll in [false, true] {
+        let started = Instant::now();
+        let err = match enroll {
+            false => client.auth_outcome(request(), |_| {}).await.unwrap_err(),
+            true => client
+                .enroll_with_poll_config(None::<String>, config, |_| {})
+                .await
+                .unwrap_err(),
+        };
+
+        assert!(matches!(err, Error::Timeout), "{err}");
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(350), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(600), "{elapsed:?}");
+    }
+
+    // One immediate poll and one per interval until the deadline
+    for path in ["/auth/v2/auth_status", "/auth/v2/enroll_status"] {
+        let polls = server.requests(path).len();
+        assert!((3..=4).contains(&polls), "{path}: {polls}");
+    }
+}
+
+#[tokio::test]
+async fn poll_config_is_overridable_per_call() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("waiting", "pushed"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    let config = PollConfig {
+        interval: Duration::from_millis(50),
+        timeout: Some(Duration::from_millis(200)),
+        ..PollConfig::default()
+    };
+
+    let started = Instant::now();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let err = client
+        .auth_with_poll_config(request, config, |_| {})
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, Error::Timeout), "{err}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(server.requests("/auth/v2/auth_status").len() >= 4);
+}
+
+#[tokio::test]
+async fn trusted_device_token_is_returned_and_accepted_by_preauth() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond(
+        "/auth/v2/auth_status",
+        MockResponse::ok(json!({
+            "result": "allow",
+            "status": "allow",
+            "status_msg": "Success. Logging you in...",
+            "trusted_device_token": "Zm9vYmFyYmF6"
+        })),
+    );
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "Remembered device" })),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let outcome = client.auth_outcome(request, |_| {}).await.unwrap();
+    assert_eq!(
+        outcome.trusted_device_token.as_deref(),
+        Some("Zm9vYmFyYmF6")
+    );
+
+    let mut preauth = PreauthRequest::new(User::username("user"));
+    preauth.trusted_device_token = outcome.trusted_device_token;
+    client.preauth(preauth).await.unwrap();
+    assert_eq!(
+        server.requests("/auth/v2/preauth")[0].body,
+        "trusted_device_token=Zm9vYmFyYmF6&username=user"
+    );
+}
+
+#[tokio::test]
+async fn accepted_token_reauths_silently() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "Remembered device" })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let outcome = client
+        .reauth_with_token(User::username("user"), "Zm9vYmFyYmF6")
+        .await
+        .unwrap();
+
+    assert!(outcome.allowed && outcome.silent);
+    assert_eq!(
+        outcome.trusted_device_token.as_deref(),
+        Some("Zm9vYmFyYmF6")
+    );
+    assert!(server.requests("/auth/v2/auth").is_empty());
+}
+
+#[tokio::test]
+async fn expired_token_falls_back_to_a_full_auth() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({
+            "result": "auth",
+            "status_msg": "Account is active",
+            "devices": [{
+                "device": "DPFZRS9FB0D46QFTM891",
+                "type": "phone",
+                "number": "XXX-XXX-0100",
+                "name": "",
+                "capabilities": ["push"]
+            }]
+        })),
+    );
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond(
+        "/auth/v2/auth_status",
+        MockResponse::ok(json!({
+            "result": "allow",
+            "status": "allow",
+            "status_msg": "Success. Logging you in...",
+            "trusted_device_token": "bmV3dG9rZW4="
+        })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let outcome = client
+        .reauth_with_token(User::username("user"), "Zm9vYmFyYmF6")
+        .await
+        .unwrap();
+
+    assert!(outcome.allowed && !outcome.silent);
+    assert_eq!(
+        outcome.trusted_device_token.as_deref(),
+        Some("bmV3dG9rZW4=")
+    );
+    assert_eq!(
+        server.requests("/auth/v2/auth")[0].body,
+        "async=1&device=auto&factor=auto&username=user"
+    );
+}
+
+fn slow_poll_client(server: &MockDuoServer, poll_timeout: Duration) -> DuoClient {
+    DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_timeouts(Timeouts {
+            auth_status: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        })
+        .with_poll_interval(Duration::from_millis(50))
+        .with_poll_timeout(poll_timeout)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn timed_out_poll_is_polled_again() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond(
+            "/auth/v2/auth_status",
+            auth_status("waiting", "pushed").delayed(Duration::from_millis(300)),
+        )
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = slow_poll_client(&server, Duration::from_secs(5));
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    assert!(client.auth_wait(request).await.unwrap());
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), 2);
+}
+
+#[tokio::test]
+async fn timed_out_polls_never_fail_open() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond(
+        "/auth/v2/auth_status",
+        auth_status("waiting", "pushed").delayed(Duration::from_millis(300)),
+    );
+
+    let client = slow_poll_client(&server, Duration::from_millis(500));
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let result = client
+        .authenticate_with_failmode(request, FailMode::Safe)
+        .await;
+
+    assert!(matches!(result, Err(Error::Timeout)), "{result:?}");
+    assert!(server.requests("/auth/v2/auth_status").len() >= 3);
+}
+
+#[tokio::test]
+async fn auth_any_device_takes_one_poll_slot() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server
+        .respond("/auth/v2/auth_status", auth_status("waiting", "pushed"))
+        .respond("/auth/v2/auth_status", auth_status("waiting", "pushed"))
+        .respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(200))
+        .with_max_concurrent_polls(1)
+        .fail_when_busy(true)
+        .build()
+        .unwrap();
+
+    let login = client.auth_any_device(
+        User::username("user"),
+        vec!["PHONE1".into(), "PHONE2".into()],
+    );
+    let other = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let request = AuthRequest::new(User::username("other"), AuthRequestFactor::auto());
+        client.auth_wait(request).await
+    };
+    let (allowed, other) = tokio::join!(login, other);
+
+    assert!(allowed.unwrap());
+    // Both devices were pushed under the single slot, which kept other logins out
+    assert_eq!(server.requests("/auth/v2/auth").len(), 2);
+    let other = other.unwrap_err();
+    assert!(matches!(other.downcast_ref(), Some(Error::Busy)), "{other}");
+}
+
+/// Server pushing to `PHONE1` and `PHONE2` as transactions `TX1` and `TX2`, answering their
+/// polls with `first` and `second` every 50ms
+async fn two_phones(first: MockResponse, second: MockResponse) -> MockDuoServer {
+    let server = MockDuoServer::start().await;
+    server
+        .respond_matching(
+            "/auth/v2/auth",
+            "device=PHONE1",
+            MockResponse::ok(json!({ "txid": "TX1" })),
+        )
+        .respond_matching(
+            "/auth/v2/auth",
+            "device=PHONE2",
+            MockResponse::ok(json!({ "txid": "TX2" })),
+        );
+    let poll_delay = Duration::from_millis(50);
+    server
+        .respond_matching(
+            "/auth/v2/auth_status",
+            "txid=TX1",
+            first.delayed(poll_delay),
+        )
+        .respond_matching(
+            "/auth/v2/auth_status",
+            "txid=TX2",
+            second.delayed(poll_delay),
+        );
+    server
+}
+
+fn any_device_client(server: &MockDuoServer) -> DuoClient {
+    DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(50))
+        .with_poll_timeout(Duration::from_secs(5))
+        .count_outcomes(true)
+        .build()
+        .unwrap()
+}
+
+fn polls_of(server: &MockDuoServer, txid: &str) -> usize {
+    server
+        .requests("/auth/v2/auth_status")
+        .iter()
+        .filter(|r| r.query.as_deref() == Some(&format!("txid={txid}")))
+        .count()
+}
+
+async fn auth_both(client: &DuoClient) -> Result<bool, Error> {
+    client
+        .auth_any_device(
+            User::username("user"),
+            vec!["PHONE1".into(), "PHONE2".into()],
+        )
+        .await
+}
+
+#[tokio::test]
+async fn first_approval_wins_and_stops_the_other_polls() {
+    let server = two_phones(
+        auth_status("allow", "allow"),
+        auth_status("waiting", "pushed"),
+    )
+    .await;
+    let client = any_device_client(&server);
+
+    assert!(auth_both(&client).await.unwrap());
+
+    let polls = polls_of(&server, "TX2");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(polls_of(&server, "TX2"), polls);
+    assert_eq!(client.stats().allowed, 1);
+    assert_eq!(client.stats().denied, 0);
+}
+
+#[tokio::test]
+async fn denial_keeps_waiting_on_the_other_devices() {
+    let server = two_phones(
+        auth_status("deny", "deny"),
+        auth_status("waiting", "pushed"),
+    )
+    .await;
+    server.respond_matching(
+        "/auth/v2/auth_status",
+        "txid=TX2",
+        auth_status("waiting", "pushed"),
+    );
+    server.respond_matching(
+        "/auth/v2/auth_status",
+        "txid=TX2",
+        auth_status("allow", "allow"),
+    );
+    let client = any_device_client(&server);
+
+    assert!(auth_both(&client).await.unwrap());
+
+    // A single outcome for the login, the denial on PHONE1 isn't counted
+    let stats = client.stats();
+    assert_eq!((stats.allowed, stats.denied), (1, 0));
+}
+
+#[tokio::test]
+async fn unanimous_denial_denies() {
+    let server = two_phones(auth_status("deny", "deny"), auth_status("deny", "deny")).await;
+    let client = any_device_client(&server);
+
+    assert!(!auth_both(&client).await.unwrap());
+    assert_eq!(client.stats().denied, 1);
+}
+
+#[tokio::test]
+async fn failed_device_without_approval_is_an_error() {
+    let server = two_phones(
+        auth_status("deny", "deny"),
+        MockResponse::json(
+            400,
+            json!({ "stat": "FAIL", "code": 40002, "message": "Invalid request parameters", "message_detail": "txid" }),
+        ),
+    )
+    .await;
+    let client = any_device_client(&server);
+
+    let result = auth_both(&client).await;
+
+    assert!(
+        matches!(&result, Err(Error::InvalidTransaction { txid, .. }) if txid == "TX2"),
+        "{result:?}"
+    );
+    assert_eq!(client.stats().failed, 1);
+}
+
+#[tokio::test]
+async fn auth_any_device_needs_a_device() {
+    let server = MockDuoServer::start().await;
+    let client = any_device_client(&server);
+
+    let result = client
+        .auth_any_device(User::username("user"), Vec::new())
+        .await;
+
+    assert!(matches!(result, Err(Error::NoDevices)), "{result:?}");
+    assert!(server.requests("/auth/v2/auth").is_empty());
+}
+
+#[tokio::test]
+async fn spawned_auth_delivers_the_decision() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("allow", "allow"));
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let (handle, decision) = client.spawn_auth(request);
+
+    assert!(decision.await.unwrap().unwrap());
+    assert!(handle.is_finished());
+}
+
+#[tokio::test]
+async fn dropping_the_handle_stops_polling() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond(
+        "/auth/v2/auth_status",
+        auth_status("waiting", "pushed").delayed(Duration::from_millis(50)),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let (handle, decision) = client.spawn_auth(request);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!server.requests("/auth/v2/auth_status").is_empty());
+    drop(handle);
+
+    assert!(decision.await.is_err());
+    let polls = server.requests("/auth/v2/auth_status").len();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), polls);
+}
+
+#[tokio::test]
+async fn per_call_poll_config_is_validated() {
+    let server = MockDuoServer::start().await;
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let request = || AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let busy_loop = PollConfig {
+        interval: Duration::ZERO,
+        ..PollConfig::default()
+    };
+    let too_short = PollConfig {
+        interval: Duration::from_secs(10),
+        timeout: Some(Duration::from_secs(5)),
+        idle_timeout: None,
+    };
+
+    let errors = [
+        client
+            .auth_with_poll_config(request(), busy_loop, |_| {})
+            .await
+            .unwrap_err(),
+        client
+            .enroll_with_poll_config(None::<String>, too_short, |_| {})
+            .await
+            .unwrap_err(),
+        client
+            .auth_with_idle_timeout(
+                request(),
+                Duration::from_secs(1),
+                Duration::from_millis(500),
+                |_| {},
+            )
+            .await
+            .unwrap_err(),
+    ];
+
+    for err in errors {
+        assert!(matches!(err, Error::InvalidConfig { .. }), "{err}");
+    }
+    assert!(server.requests("/auth/v2/auth").is_empty());
+    assert!(server.requests("/auth/v2/enroll").is_empty());
+}
+
+#[tokio::test]
+async fn shutdown_stops_polling_and_refuses_new_auths() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    server.respond("/auth/v2/auth_status", auth_status("waiting", "pushed"));
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_poll_interval(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let request = || AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+
+    let waiting = client.auth_wait(request());
+    let shutdown = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        client.shutdown();
+        Instant::now()
+    };
+    let (result, shut_down_at) = tokio::join!(waiting, shutdown);
+
+    // Returned right after the current poll instead of waiting out the interval
+    let err = result.unwrap_err();
+    assert!(
+        matches!(err.downcast_ref(), Some(Error::ShuttingDown)),
+        "{err}"
+    );
+    assert!(shut_down_at.elapsed() < Duration::from_secs(1));
+    assert_eq!(server.requests("/auth/v2/auth_status").len(), 1);
+
+    let err = client.auth(request()).await.unwrap_err();
+    assert!(matches!(err, Error::ShuttingDown), "{err}");
+    assert_eq!(server.requests("/auth/v2/auth").len(), 1);
+}
@@ -0,0 +1,269 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::{DuoErrorCode, Error, RequestContext},
+    types::{Device, DeviceCapability, PreauthRequest, PreauthResult, User},
+    DuoClient,
+};
+use serde_json::json;
+
+fn auth_response() -> MockResponse {
+    MockResponse::ok(json!({
+        "result": "auth",
+        "status_msg": "Account is active",
+        "devices": [{
+            "device": "DPFZRS9FB0D46QFTM891",
+            "type": "phone",
+            "number": "XXX-XXX-0100",
+            "name": "",
+            "capabilities": ["push", "sms"]
+        }]
+    }))
+}
+
+fn preauth() -> PreauthRequest {
+    PreauthRequest::new(User::username("user"))
+}
+
+#[tokio::test]
+async fn falls_back_to_cached_auth_response() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/preauth", auth_response());
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::fail(503, 50301, "Service unavailable"),
+    );
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .allow_stale_preauth(Duration::from_secs(60))
+        .build()
+        .unwrap();
+
+    let fresh = client.preauth_or_stale(preauth()).await.unwrap();
+    assert!(!fresh.is_stale());
+
+    let stale = client.preauth_or_stale(preauth()).await.unwrap();
+    assert!(stale.is_stale());
+    assert_eq!(stale.response.result(), PreauthResult::Auth);
+}
+
+#[tokio::test]
+async fn deny_is_never_served_stale() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/preauth", auth_response());
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "deny", "status_msg": "Account is locked" })),
+    );
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::fail(503, 50301, "Service unavailable"),
+    );
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .allow_stale_preauth(Duration::from_secs(60))
+        .build()
+        .unwrap();
+
+    client.preauth_or_stale(preauth()).await.unwrap();
+    let denied = client.preauth_or_stale(preauth()).await.unwrap();
+    assert_eq!(denied.response.result(), PreauthResult::Deny);
+
+    let err = client.preauth_or_stale(preauth()).await.unwrap_err();
+    assert!(err.is_unavailable());
+}
+
+#[tokio::test]
+async fn preauth_by_username_exposes_user_id() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({
+                "result": "allow",
+                "status_msg": "",
+                "user_id": "DU94SWSN4ADHHJHF2HXT"
+            })),
+        )
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({ "result": "allow", "status_msg": "" })),
+        );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let response = client.preauth(preauth()).await.unwrap();
+    assert_eq!(response.user_id(), Some("DU94SWSN4ADHHJHF2HXT"));
+
+    let response = client.preauth(preauth()).await.unwrap();
+    assert_eq!(response.user_id(), None);
+}
+
+#[tokio::test]
+async fn api_errors_keep_their_variant_and_name_the_request() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::fail(400, 40002, "Invalid request parameters"),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.preauth(preauth()).await.unwrap_err();
+
+    assert!(
+        matches!(
+            &err,
+            Error::ApiRequestFailed {
+                code: DuoErrorCode::InvalidParameters,
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+    assert_eq!(
+        err.context(),
+        Some(&RequestContext {
+            endpoint: "preauth".into(),
+            user: Some("username=user".into()),
+        })
+    );
+    assert_eq!(
+        err.to_string(),
+        "API request failed: Invalid request parameters (40002), during preauth for username=user"
+    );
+}
+
+#[tokio::test]
+async fn strongest_factor_is_recommended_across_devices() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({
+            "result": "auth",
+            "status_msg": "Account is active",
+            "devices": [
+                {
+                    "device": "LANDLINE",
+                    "type": "phone",
+                    "name": "",
+                    "number": "",
+                    "capabilities": ["phone", "sms"]
+                },
+                {
+                    "device": "SMARTPHONE",
+                    "type": "phone",
+                    "name": "",
+                    "number": "",
+                    "capabilities": ["sms", "push"]
+                }
+            ]
+        })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let (device, capability) = client
+        .recommend_device(User::username("user"), &DeviceCapability::STRENGTH_ORDER)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (device.device.as_str(), capability),
+        ("SMARTPHONE", DeviceCapability::Push)
+    );
+
+    let devices = client.list_devices(User::username("user")).await.unwrap();
+    let preference = [DeviceCapability::Phone, DeviceCapability::Push];
+    let (device, capability) = Device::recommend(&devices, &preference).unwrap();
+    assert_eq!(
+        (device.device.as_str(), capability),
+        ("LANDLINE", DeviceCapability::Phone)
+    );
+    assert!(Device::recommend(&devices, &[DeviceCapability::MobileOtp]).is_none());
+
+    let factors = client
+        .available_factors(User::username("user"))
+        .await
+        .unwrap();
+    assert_eq!(
+        factors,
+        [
+            DeviceCapability::Push,
+            DeviceCapability::Phone,
+            DeviceCapability::Sms
+        ]
+    );
+}
+
+#[tokio::test]
+async fn only_users_asked_to_enroll_are_unenrolled() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({
+                "result": "enroll",
+                "status_msg": "Enroll an authentication device to proceed",
+                "enroll_portal_url": "https://api-example.duosecurity.com/portal?code=48bac5d9393fb2c2",
+            })),
+        )
+        .respond("/auth/v2/preauth", auth_response())
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({ "result": "allow", "status_msg": "Allowing unknown user" })),
+        )
+        .respond(
+            "/auth/v2/preauth",
+            MockResponse::ok(json!({ "result": "deny", "status_msg": "Account is locked" })),
+        );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let mut enrolled = Vec::new();
+    for _ in 0..4 {
+        enrolled.push(client.is_enrolled(User::username("user")).await.unwrap());
+    }
+
+    assert_eq!(enrolled, [false, true, true, true]);
+}
+
+#[tokio::test]
+async fn preauth_result_skips_the_devices() {
+    let server = MockDuoServer::start().await;
+    // Not a valid device, only the full preauth response fails on it
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({
+            "result": "auth",
+            "status_msg": "Account is active",
+            "devices": [{ "device": "DPFZRS9FB0D46QFTM891" }]
+        })),
+    );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let result = client.preauth_result(preauth()).await.unwrap();
+
+    assert_eq!(result, PreauthResult::Auth);
+    assert!(client.preauth(preauth()).await.is_err());
+}
+
+#[tokio::test]
+async fn raw_preauth_keeps_unmodelled_fields() {
+    let server = MockDuoServer::start().await;
+    let response = json!({
+        "result": "auth",
+        "status": "auth",
+        "status_msg": "Account is active",
+        "devices": [],
+        "risk": { "level": "elevated" },
+    });
+    server.respond("/auth/v2/preauth", MockResponse::ok(response.clone()));
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let raw = client.preauth_raw(preauth()).await.unwrap();
+
+    assert_eq!(raw, response);
+    assert_eq!(server.requests("/auth/v2/preauth")[0].body, "username=user");
+}
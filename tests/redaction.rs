@@ -0,0 +1,242 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    request::REDACTED,
+    types::{AuthRequest, AuthRequestFactor, PreauthRequest, User},
+    DuoClient,
+};
+use serde_json::json;
+
+/// Secrets of the requests recorded by `server`: the skey, every `Authorization` header value
+/// and the signature within it
+fn secrets(server: &MockDuoServer, paths: &[&str]) -> Vec<String> {
+    let mut secrets = vec![SKEY.to_string()];
+    for request in paths.iter().flat_map(|path| server.requests(path)) {
+        let header = request.headers["authorization"].clone();
+        let encoded = header.strip_prefix("Basic ").unwrap();
+        let decoded = String::from_utf8(STANDARD.decode(encoded).unwrap()).unwrap();
+
+        secrets.push(decoded.split_once(':').unwrap().1.to_string());
+        secrets.push(encoded.to_string());
+        secrets.push(header);
+    }
+    secrets
+}
+
+fn assert_redacted(output: &str, secrets: &[String]) {
+    for secret in secrets {
+        assert!(
+            !output.contains(secret.as_str()),
+            "'{secret}' leaked into: {output}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn api_errors_leak_no_secrets() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::fail(401, 40103, "Invalid signature in request credentials"),
+    );
+    server.respond(
+        "/auth/v2/auth",
+        MockResponse::fail(400, 40002, "Invalid request parameters"),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let preauth = PreauthRequest::new(User::username("user"));
+    let auth = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let errors = [
+        client.preauth(preauth).await.unwrap_err(),
+        client.auth(auth).await.unwrap_err(),
+    ];
+
+    let secrets = secrets(&server, &["/auth/v2/preauth", "/auth/v2/auth"]);
+    assert_eq!(secrets.len(), 7);
+    for err in errors {
+        assert_redacted(&format!("{err} {err:?}"), &secrets);
+    }
+}
+
+#[tokio::test]
+async fn unexpected_responses_leak_no_secrets() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::json(502, json!("<html>Bad Gateway</html>")),
+    );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.check().await.unwrap_err();
+
+    let secrets = secrets(&server, &["/auth/v2/check"]);
+    assert_redacted(&format!("{err} {err:?}"), &secrets);
+}
+
+#[tokio::test]
+async fn connection_errors_leak_no_secrets() {
+    let client = DuoClient::new("http://127.0.0.1:1", IKEY, SKEY).unwrap();
+
+    let err = client.check().await.unwrap_err();
+
+    assert_redacted(&format!("{err} {err:?}"), &[SKEY.to_string()]);
+}
+
+#[tokio::test]
+async fn exchanges_and_canonical_requests_leak_no_secrets() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "" })),
+    );
+
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .on_exchange({
+            let logged = Arc::clone(&logged);
+            move |exchange| logged.lock().unwrap().push(format!("{exchange:?}"))
+        })
+        .on_canonical_request({
+            let logged = Arc::clone(&logged);
+            move |canonical| logged.lock().unwrap().push(format!("{canonical:?}"))
+        })
+        .build()
+        .unwrap();
+
+    client
+        .preauth(PreauthRequest::new(User::username("user")))
+        .await
+        .unwrap();
+
+    let secrets = secrets(&server, &["/auth/v2/preauth"]);
+    let logged = logged.lock().unwrap();
+    assert_eq!(logged.len(), 2);
+    for output in logged.iter() {
+        assert_redacted(output, &secrets);
+    }
+}
+
+#[tokio::test]
+async fn canonical_requests_redact_secret_parameters() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/preauth",
+        MockResponse::ok(json!({ "result": "allow", "status_msg": "" })),
+    );
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "TX1" })));
+
+    let canonical = Arc::new(Mutex::new(Vec::new()));
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .on_canonical_request({
+            let canonical = Arc::clone(&canonical);
+            move |request| canonical.lock().unwrap().push(request.clone())
+        })
+        .build()
+        .unwrap();
+
+    let mut preauth = PreauthRequest::new(User::username("user"));
+    preauth.trusted_device_token = Some("TRUSTEDTOKEN".into());
+    client.preauth(preauth).await.unwrap();
+    client
+        .auth(AuthRequest::new(
+            User::username("user"),
+            AuthRequestFactor::passcode("314159"),
+        ))
+        .await
+        .unwrap();
+
+    let canonical = canonical.lock().unwrap();
+    assert_eq!(canonical[0].parameters["trusted_device_token"], REDACTED);
+    assert_eq!(canonical[1].parameters["passcode"], REDACTED);
+    assert!(canonical[1]
+        .canonical_string
+        .contains("passcode=%5Bredacted%5D"));
+
+    // Still sent as is
+    assert!(server.requests("/auth/v2/auth")[0]
+        .body
+        .contains("passcode=314159"));
+    let secrets = ["TRUSTEDTOKEN".to_string(), "314159".to_string()];
+    for request in canonical.iter() {
+        assert_redacted(&format!("{request:?}"), &secrets);
+    }
+}
+
+#[tokio::test]
+async fn exchanges_redact_passcodes_and_activation_codes() {
+    let server = MockDuoServer::start().await;
+    server.respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "TX1" })));
+    server.respond(
+        "/auth/v2/enroll",
+        MockResponse::ok(json!({
+            "activation_barcode": "https://api-abcdef.duosecurity.com/frame/qr?value=ACTIVATE",
+            "activation_code": "duo://ACTIVATE",
+            "activation_url": "https://m-abcdef.duosecurity.com/activate/ACTIVATE",
+            "expiration": 1357020061,
+            "user_id": "DU94SWSN4ADHHJHF2HXT",
+            "username": "alice",
+        })),
+    );
+    server.respond("/auth/v2/enroll_status", MockResponse::ok(json!("waiting")));
+
+    let exchanges = Arc::new(Mutex::new(Vec::new()));
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .on_exchange({
+            let exchanges = Arc::clone(&exchanges);
+            move |exchange| exchanges.lock().unwrap().push(exchange.clone())
+        })
+        .build()
+        .unwrap();
+
+    client
+        .auth(AuthRequest::new(
+            User::username("user"),
+            AuthRequestFactor::passcode("314159"),
+        ))
+        .await
+        .unwrap();
+    let enrollment = client.enroll(Some("alice"), None).await.unwrap();
+    client
+        .enroll_status(enrollment.user_id, enrollment.activation_code)
+        .await
+        .unwrap();
+
+    let exchanges = exchanges.lock().unwrap();
+    let [auth, enroll, enroll_status] = &exchanges[..] else {
+        panic!("{exchanges:?}");
+    };
+    let body = |body: &[u8]| String::from_utf8(body.to_vec()).unwrap();
+
+    let auth_body = body(auth.request.body.as_deref().unwrap());
+    assert!(auth_body.contains("passcode=%5Bredacted%5D"), "{auth_body}");
+    assert!(auth_body.contains("username=user"), "{auth_body}");
+
+    let enrolled: serde_json::Value =
+        serde_json::from_slice(&enroll.response.as_ref().unwrap().body).unwrap();
+    assert_eq!(enrolled["response"]["activation_code"], REDACTED);
+    assert_eq!(enrolled["response"]["activation_url"], REDACTED);
+    assert_eq!(enrolled["response"]["user_id"], "DU94SWSN4ADHHJHF2HXT");
+
+    // Untouched responses are kept byte for byte
+    assert_eq!(
+        body(&enroll_status.response.as_ref().unwrap().body),
+        r#"{"response":"waiting","stat":"OK"}"#
+    );
+
+    let secrets = ["314159".to_string(), "ACTIVATE".to_string()];
+    for exchange in exchanges.iter() {
+        let request_body = body(exchange.request.body.as_deref().unwrap_or_default());
+        let response_body = body(&exchange.response.as_ref().unwrap().body);
+        assert_redacted(
+            &format!("{} {request_body} {response_body}", exchange.request.url),
+            &secrets,
+        );
+    }
+}
@@ -0,0 +1,387 @@
+mod common;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{
+    errors::{DuoErrorCode, Error},
+    DuoClient,
+};
+use serde_json::json;
+
+async fn flaky_server() -> MockDuoServer {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(503, 50301, "Service unavailable"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+    server
+}
+
+#[tokio::test]
+async fn service_unavailable_is_retried_when_enabled() {
+    let server = flaky_server().await;
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_retries(1)
+        .build()
+        .unwrap();
+
+    let time = client.check().await.unwrap();
+
+    assert_eq!(time.as_secs(), 1357020061);
+    assert_eq!(server.requests("/auth/v2/check").len(), 2);
+}
+
+#[tokio::test]
+async fn rate_limited_requests_are_retried() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(429, 42901, "Too many requests"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_retries(1)
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+
+    assert_eq!(server.requests("/auth/v2/check").len(), 2);
+}
+
+#[cfg(feature = "poll-loop")]
+#[tokio::test]
+async fn auth_creation_is_never_retried() {
+    use duo_auth::types::{AuthRequest, AuthRequestFactor, User};
+
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/auth",
+            MockResponse::fail(502, 50201, "Bad gateway"),
+        )
+        .respond("/auth/v2/auth", MockResponse::ok(json!({ "txid": "txid" })));
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .with_retries(3)
+        .build()
+        .unwrap();
+
+    let request = AuthRequest::new(User::username("user"), AuthRequestFactor::auto());
+    let err = client.auth(request).await.unwrap_err();
+
+    assert!(
+        matches!(&err, Error::AuthCreationUncertain { .. }),
+        "{err:?}"
+    );
+    assert!(err.is_unavailable());
+    assert_eq!(server.requests("/auth/v2/auth").len(), 1);
+}
+
+#[tokio::test]
+async fn service_unavailable_fails_without_retries() {
+    let server = flaky_server().await;
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let result = client.check().await;
+
+    let err = result.unwrap_err();
+    assert!(
+        matches!(&err, Error::ServiceUnavailable { status: 503, .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(
+        err.to_string(),
+        "Duo is unavailable (HTTP 503), during check"
+    );
+    assert_eq!(server.requests("/auth/v2/check").len(), 1);
+}
+
+#[tokio::test]
+async fn rejected_credentials_halt_until_replaced() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(401, 40103, "Invalid signature in request credentials"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .halt_on_invalid_credentials(true)
+        .build()
+        .unwrap();
+
+    for _ in 0..2 {
+        let err = client.check().await.unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::InvalidCredentials {
+                code: DuoErrorCode::InvalidSignature,
+                ..
+            }
+        ));
+    }
+    assert_eq!(server.requests("/auth/v2/check").len(), 1);
+
+    client.set_credentials(IKEY, SKEY);
+    client.check().await.unwrap();
+    assert_eq!(server.requests("/auth/v2/check").len(), 2);
+}
+
+fn signature_len(request: &common::RecordedRequest) -> usize {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let encoded = request.headers["authorization"]
+        .strip_prefix("Basic ")
+        .unwrap();
+    let decoded = String::from_utf8(STANDARD.decode(encoded).unwrap()).unwrap();
+    decoded.split_once(':').unwrap().1.len()
+}
+
+#[tokio::test]
+async fn rejected_sha1_signature_upgrades_to_sha512() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(401, 40103, "Invalid signature in request credentials"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .upgrade_signature_algorithm(true)
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+    client.check().await.unwrap();
+
+    let lengths: Vec<_> = server
+        .requests("/auth/v2/check")
+        .iter()
+        .map(signature_len)
+        .collect();
+    // Hex encoded HMAC-SHA1, then HMAC-SHA512 from there on
+    assert_eq!(lengths, [40, 128, 128]);
+}
+
+#[tokio::test]
+async fn errors_are_classified_as_retriable() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(429, 42901, "Too many requests"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(503, 50301, "Service unavailable"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(401, 40103, "Invalid signature in request credentials"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(400, 40002, "Invalid request parameters"),
+        );
+
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    assert!(client.check().await.unwrap_err().is_retriable());
+    assert!(client.check().await.unwrap_err().is_retriable());
+    assert!(!client.check().await.unwrap_err().is_retriable());
+    assert!(!client.check().await.unwrap_err().is_retriable());
+
+    let unreachable = DuoClient::new("http://127.0.0.1:1", IKEY, SKEY).unwrap();
+    assert!(unreachable.check().await.unwrap_err().is_retriable());
+
+    let gateway = |status| Error::UnexpectedResponse {
+        status,
+        body: String::new(),
+        context: None,
+    };
+    assert!(Error::Timeout.is_retriable());
+    assert!(gateway(502).is_retriable());
+    assert!(gateway(429).is_retriable());
+    assert!(!gateway(403).is_retriable());
+    assert!(!Error::AuthCreationUncertain {
+        cause: "connection reset".into(),
+        context: None,
+    }
+    .is_retriable());
+}
+
+fn date_header(request: &common::RecordedRequest) -> i64 {
+    chrono::DateTime::parse_from_rfc2822(&request.headers["date"])
+        .unwrap()
+        .timestamp()
+}
+
+#[tokio::test]
+async fn clock_skew_is_resynced_and_retried() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(401, 40103, "Invalid signature in request credentials"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::ok(json!({ "time": 1357020061 })),
+        );
+    let server_time = chrono::Utc::now().timestamp() + 3600;
+    server.respond(
+        "/auth/v2/ping",
+        MockResponse::ok(json!({ "time": server_time })),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .retry_on_clock_skew(true)
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+
+    let checks = server.requests("/auth/v2/check");
+    assert_eq!(checks.len(), 2);
+    assert_eq!(server.requests("/auth/v2/ping").len(), 1);
+    let skew = date_header(&checks[1]) - date_header(&checks[0]);
+    assert!((3595..=3605).contains(&skew), "{skew}");
+}
+
+#[tokio::test]
+async fn failed_resync_keeps_invalid_credentials() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::fail(401, 40103, "Invalid signature in request credentials"),
+    );
+    server.respond(
+        "/auth/v2/ping",
+        MockResponse::fail(503, 50301, "Service unavailable"),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .retry_on_clock_skew(true)
+        .build()
+        .unwrap();
+
+    let err = client.check().await.unwrap_err();
+
+    assert!(
+        matches!(
+            &err,
+            Error::InvalidCredentials {
+                code: DuoErrorCode::InvalidSignature,
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+    assert_eq!(server.requests("/auth/v2/check").len(), 1);
+    assert_eq!(server.requests("/auth/v2/ping").len(), 1);
+}
+
+#[tokio::test]
+async fn synced_time_offsets_the_date_header() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+    let server_time = chrono::Utc::now().timestamp() - 7200;
+    server.respond(
+        "/auth/v2/ping",
+        MockResponse::ok(json!({ "time": server_time })),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .sync_time(true)
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+    client.check().await.unwrap();
+
+    // Synced once up front, not per request
+    assert_eq!(server.requests("/auth/v2/ping").len(), 1);
+    for check in server.requests("/auth/v2/check") {
+        let skew = date_header(&check) - server_time;
+        assert!((-5..=5).contains(&skew), "{skew}");
+    }
+}
+
+#[tokio::test]
+async fn failed_time_sync_signs_with_the_local_clock() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+    server.respond(
+        "/auth/v2/ping",
+        MockResponse::fail(503, 50301, "Service unavailable"),
+    );
+    let client = DuoClient::builder(server.url(), IKEY, SKEY)
+        .sync_time(true)
+        .build()
+        .unwrap();
+
+    client.check().await.unwrap();
+    client.check().await.unwrap();
+
+    // Not retried on every request while Duo is struggling
+    assert_eq!(server.requests("/auth/v2/ping").len(), 1);
+    let checks = server.requests("/auth/v2/check");
+    let skew = date_header(&checks[0]) - chrono::Utc::now().timestamp();
+    assert!((-5..=5).contains(&skew), "{skew}");
+}
+
+#[tokio::test]
+async fn duo_error_codes_are_typed() {
+    let server = MockDuoServer::start().await;
+    server
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(429, 42901, "Too many requests"),
+        )
+        .respond(
+            "/auth/v2/check",
+            MockResponse::fail(400, 40099, "Something new"),
+        );
+    let client = DuoClient::new(server.url(), IKEY, SKEY).unwrap();
+
+    let err = client.check().await.unwrap_err();
+    assert_eq!(err.duo_code(), Some(DuoErrorCode::RateLimited));
+    assert_eq!(
+        err.to_string(),
+        "API request failed: Too many requests (42901), during check"
+    );
+
+    let err = client.check().await.unwrap_err();
+    assert_eq!(err.duo_code(), Some(DuoErrorCode::Unknown(40099)));
+    assert!(Error::Timeout.duo_code().is_none());
+
+    for code in [
+        40001, 40002, 40101, 40102, 40103, 40301, 40401, 40501, 42901, 50000,
+    ] {
+        assert_eq!(DuoErrorCode::from_code(code).code(), code);
+    }
+    assert_eq!(
+        DuoErrorCode::from_code(40103),
+        DuoErrorCode::InvalidSignature
+    );
+}
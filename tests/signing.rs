@@ -0,0 +1,206 @@
+use chrono::{TimeZone, Utc};
+use duo_auth::request::{
+    authorization_header, BodyEncoding, DuoRequest, Parameters, SignatureAlgorithm,
+};
+use reqwest::{Client, Method, Request, Url};
+
+// Example from Duo's API documentation ("Authentication" section)
+const SKEY: &str = "Zh5eGmUq9zpfQnyUIu5OL9iWoMMv5ZNmk3zLJ4Ep";
+const CANONICAL: &str = "Tue, 21 Aug 2012 17:29:18 -0000
+POST
+api-xxxxxxxx.duosecurity.com
+/accounts/v1/account/list
+realname=First%20Last&username=root";
+
+fn reference_request() -> DuoRequest {
+    let mut parameters = Parameters::default();
+    parameters.set("username", "root");
+    parameters.set("realname", "First Last");
+
+    DuoRequest::new(
+        Url::parse("https://API-XXXXXXXX.duosecurity.com").unwrap(),
+        Method::POST,
+        "/accounts/v1/account/list",
+        parameters,
+    )
+    .with_date(Utc.with_ymd_and_hms(2012, 8, 21, 17, 29, 18).unwrap())
+}
+
+#[test]
+fn hmac_sha1_reference_vector() {
+    let debug = reference_request().debug_signature(SKEY).unwrap();
+
+    assert_eq!(debug.date, "Tue, 21 Aug 2012 17:29:18 -0000");
+    assert_eq!(debug.canonical_string, CANONICAL);
+    assert_eq!(debug.signature, "2d97d6166319781b5a3a07af39d366f491234edc");
+}
+
+#[test]
+fn hmac_sha512_reference_vector() {
+    // Computed with Python's hmac module over the same canonical string
+    let debug = reference_request()
+        .with_signature_algorithm(SignatureAlgorithm::HmacSha512)
+        .debug_signature(SKEY)
+        .unwrap();
+
+    assert_eq!(debug.canonical_string, CANONICAL);
+    assert_eq!(
+        debug.signature,
+        "1bffb8924348f7db7da9e7d7d749ded93aafd02d8e904960da7b96c57510220\
+         185a64a2827ee8f24ac5d30802a9ee97e59de44b304b18242f0d596d15a8122af"
+    );
+}
+
+const IKEY: &str = "DIWJ8X6AEYOR5OMC6TQ1";
+const TXID: &str = "45f7c92b-f45f-4862-8545-e0f58e78075a";
+
+fn auth_status_duo_request(method: Method) -> DuoRequest {
+    let mut parameters = Parameters::default();
+    parameters.set("txid", TXID);
+
+    DuoRequest::new(
+        Url::parse("https://api-xxxxxxxx.duosecurity.com").unwrap(),
+        method,
+        "/auth/v2/auth_status",
+        parameters,
+    )
+    .with_date(Utc.with_ymd_and_hms(2012, 8, 21, 17, 29, 18).unwrap())
+}
+
+fn auth_status_request(method: Method) -> Request {
+    auth_status_duo_request(method)
+        .build(&Client::new(), IKEY, SKEY)
+        .unwrap()
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+#[test]
+fn authorization_header_encodes_ikey_and_signature() {
+    assert_eq!(
+        authorization_header(IKEY, "2d97d6166319781b5a3a07af39d366f491234edc"),
+        "Basic RElXSjhYNkFFWU9SNU9NQzZUUTE6MmQ5N2Q2MTY2MzE5NzgxYjVhM2EwN2FmMzlkMzY2ZjQ5MTIzNGVkYw=="
+    );
+    assert_eq!(authorization_header("", ""), "Basic Og==");
+}
+
+#[test]
+fn authorization_header_is_sent_as_built() {
+    let request = auth_status_request(Method::GET);
+    let debug = auth_status_duo_request(Method::GET)
+        .debug_signature(SKEY)
+        .unwrap();
+
+    assert_eq!(
+        header(&request, "Authorization"),
+        Some(authorization_header(IKEY, &debug.signature).as_str())
+    );
+    assert!(request.headers()["Authorization"].is_sensitive());
+}
+
+#[test]
+fn get_parameters_are_signed_in_query() {
+    let request = auth_status_request(Method::GET);
+
+    assert_eq!(
+        request.url().query(),
+        Some(format!("txid={}", TXID).as_str())
+    );
+    assert!(request.body().is_none());
+    assert_eq!(header(&request, "Content-Type"), None);
+    assert_eq!(
+        header(&request, "Date"),
+        Some("Tue, 21 Aug 2012 17:29:18 -0000")
+    );
+    // ikey:79c206ff7d059d1e588ea9a83c92bbe86fbd9ae5
+    assert_eq!(
+        header(&request, "Authorization"),
+        Some("Basic RElXSjhYNkFFWU9SNU9NQzZUUTE6NzljMjA2ZmY3ZDA1OWQxZTU4OGVhOWE4M2M5MmJiZTg2ZmJkOWFlNQ==")
+    );
+}
+
+#[test]
+fn post_parameters_are_signed_in_body() {
+    let request = auth_status_request(Method::POST);
+
+    assert_eq!(request.url().query(), None);
+    assert_eq!(
+        request.body().and_then(|b| b.as_bytes()),
+        Some(format!("txid={}", TXID).as_bytes())
+    );
+    assert_eq!(
+        header(&request, "Content-Type"),
+        Some("application/x-www-form-urlencoded")
+    );
+    // ikey:0f2b629f8b7174896f6df6d2c322d3f4c8905c3e
+    assert_eq!(
+        header(&request, "Authorization"),
+        Some("Basic RElXSjhYNkFFWU9SNU9NQzZUUTE6MGYyYjYyOWY4YjcxNzQ4OTZmNmRmNmQyYzMyMmQzZjRjODkwNWMzZQ==")
+    );
+}
+
+#[test]
+fn get_without_parameters_has_no_query() {
+    let request = DuoRequest::new(
+        Url::parse("https://api-xxxxxxxx.duosecurity.com").unwrap(),
+        Method::GET,
+        "/auth/v2/check",
+        Parameters::default(),
+    )
+    .build(&Client::new(), IKEY, SKEY)
+    .unwrap();
+
+    assert_eq!(
+        request.url().as_str(),
+        "https://api-xxxxxxxx.duosecurity.com/auth/v2/check"
+    );
+}
+
+#[test]
+fn encoded_body_matches_python_client() {
+    let mut parameters = Parameters::default();
+    parameters.set("username", "root");
+    parameters.set("realname", "First Last");
+    parameters.set("note", "a+b/c=d&e~f*ü");
+    let request = |method| {
+        DuoRequest::new(
+            Url::parse("https://api-xxxxxxxx.duosecurity.com").unwrap(),
+            method,
+            "/auth/v2/preauth",
+            parameters.clone(),
+        )
+    };
+
+    // duo_client's canon_params: urllib.parse.quote(k/v, "~"), joined sorted by key
+    assert_eq!(
+        request(Method::POST).encoded_body().as_deref(),
+        Some("note=a%2Bb%2Fc%3Dd%26e~f%2A%C3%BC&realname=First%20Last&username=root")
+    );
+    assert_eq!(request(Method::GET).encoded_body(), None);
+}
+
+#[test]
+fn form_body_matches_duo_spec() {
+    let request = reference_request()
+        .with_body_encoding(BodyEncoding::Form)
+        .build(&Client::new(), IKEY, SKEY)
+        .unwrap();
+
+    assert_eq!(
+        header(&request, "Content-Type"),
+        Some("application/x-www-form-urlencoded")
+    );
+    assert_eq!(
+        request.body().and_then(|b| b.as_bytes()),
+        Some(&b"realname=First%20Last&username=root"[..])
+    );
+    assert_eq!(
+        reference_request().debug_signature(SKEY).unwrap(),
+        reference_request()
+            .with_body_encoding(BodyEncoding::Form)
+            .debug_signature(SKEY)
+            .unwrap()
+    );
+}
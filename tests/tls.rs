@@ -0,0 +1,107 @@
+mod common;
+
+use std::sync::Mutex;
+
+use common::{MockDuoServer, MockResponse, IKEY, SKEY};
+use duo_auth::{errors::Error, DuoClient};
+use serde_json::json;
+use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+#[tokio::test]
+async fn tls_handshake_failure_is_reported_as_tls() {
+    // Answers in plaintext, so the handshake fails
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("https://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let _ = socket
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .await;
+        }
+    });
+
+    let client = DuoClient::new(url, IKEY, SKEY).unwrap();
+
+    let err = client.check().await.unwrap_err();
+
+    assert!(matches!(&err, Error::Tls { .. }), "{:?}", err);
+    assert!(!err.is_unavailable());
+}
+
+#[cfg(feature = "dangerous-tls")]
+#[test]
+fn strict_mode_refuses_disabled_certificate_verification() {
+    let result = DuoClient::builder("https://api-xxxxxxxx.duosecurity.com", IKEY, SKEY)
+        .danger_accept_invalid_certs(true)
+        .strict(true)
+        .build();
+
+    assert!(
+        matches!(&result, Err(Error::InvalidConfig { reason }) if reason.contains("verification")),
+        "{:?}",
+        result.err()
+    );
+}
+
+#[tokio::test]
+async fn strict_mode_accepts_local_http_only_with_insecure_http() {
+    let server = MockDuoServer::start().await;
+    server.respond(
+        "/auth/v2/check",
+        MockResponse::ok(json!({ "time": 1357020061 })),
+    );
+
+    let local = DuoClient::builder(server.url(), IKEY, SKEY)
+        .strict(true)
+        .build();
+    let remote = DuoClient::builder("http://api-xxxxxxxx.duosecurity.com", IKEY, SKEY)
+        .strict(true)
+        .build();
+
+    assert!(matches!(remote, Err(Error::InvalidConfig { .. })));
+    match local {
+        Ok(client) if cfg!(feature = "insecure-http") => {
+            client.check().await.unwrap();
+        }
+        Err(Error::InvalidConfig { .. }) if !cfg!(feature = "insecure-http") => {}
+        Ok(_) => panic!("strict mode accepted plain http"),
+        Err(err) => panic!("{err}"),
+    }
+}
+
+/// Collects the warnings of every test in this binary
+struct Warnings(Mutex<Vec<String>>);
+
+impl log::Log for Warnings {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static WARNINGS: Warnings = Warnings(Mutex::new(Vec::new()));
+
+#[test]
+fn insecure_configuration_warns_outside_strict_mode() {
+    let _ = log::set_logger(&WARNINGS);
+    log::set_max_level(log::LevelFilter::Warn);
+
+    DuoClient::builder("https://api-xxxxxxxx.duosecurity.com", "DIWJ8X6", SKEY)
+        .build()
+        .unwrap();
+
+    let warnings = WARNINGS.0.lock().unwrap();
+    assert!(
+        warnings
+            .iter()
+            .any(|w| w.contains("integration key must be 20 characters")),
+        "{warnings:?}"
+    );
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "tower")]
+
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use common::{IKEY, SKEY};
+use duo_auth::{errors::Error, DuoClient};
+use tower::service_fn;
+
+#[tokio::test]
+async fn requests_go_through_transport() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&calls);
+    let service = service_fn(move |request: reqwest::Request| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(request.url().path(), "/auth/v2/check");
+        assert!(request.headers().contains_key("authorization"));
+
+        async move {
+            let response = http::Response::builder()
+                .header("content-type", "application/json")
+                .body(r#"{"stat":"OK","response":{"time":1357020061}}"#)
+                .unwrap();
+            Ok::<_, reqwest::Error>(reqwest::Response::from(response))
+        }
+    });
+
+    let client = DuoClient::builder("https://api-xxxxxxxx.duosecurity.com", IKEY, SKEY)
+        .with_transport(service)
+        .build()
+        .unwrap();
+
+    let time = client.check().await.unwrap();
+
+    assert_eq!(time.as_secs(), 1357020061);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn transport_errors_are_preserved() {
+    let service = service_fn(|_: reqwest::Request| async {
+        Err::<reqwest::Response, _>(std::io::Error::other("layer rejected"))
+    });
+
+    let client = DuoClient::builder("https://api-xxxxxxxx.duosecurity.com", IKEY, SKEY)
+        .with_transport(service)
+        .build()
+        .unwrap();
+
+    let err = client.check().await.unwrap_err();
+
+    assert!(matches!(&err, Error::Transport { .. }), "{:?}", err);
+}